@@ -0,0 +1,109 @@
+//! Compares resolving reply references through a [`MessageIndex`] against
+//! scanning the channel for every reply, as `get_prompt` used to. Both sides
+//! do the same lookup and nothing else; building the index is timed on its
+//! own.
+//!
+//! Run with `cargo bench --bench reference_lookup`.
+
+use std::{hint::black_box, time::Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use parsediscordarchive::{Message, MessageIndex, Reference};
+
+const MESSAGES: usize = 200_000;
+const WHO: u64 = 1;
+
+/// A busy channel where every third message replies to a recent one.
+fn synthetic_channel() -> Vec<Message> {
+    let start = DateTime::<Utc>::UNIX_EPOCH;
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..MESSAGES)
+        .map(|index| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let reference = (index > 1 && index % 3 == 0).then(|| Reference {
+                message_id: 1_000_000 + (index - 1 - (seed as usize % index.min(500))) as u64,
+                channel_id: None,
+                guild_id: None,
+            });
+            Message {
+                id: 1_000_000 + index as u64,
+                content: format!("message {index}"),
+                timestamp: start + Duration::seconds(index as i64),
+                author: seed % 8,
+                reference,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// The reference lookup `get_prompt` did before [`MessageIndex`] existed.
+fn linear_start(messages: &[Message], index: usize) -> usize {
+    if let Some(reference) = messages[index].reference {
+        for (position, message) in messages[0..index - 1].iter().enumerate() {
+            if message.id == reference.message_id {
+                return position;
+            }
+        }
+    }
+    index - 1
+}
+
+/// The same lookup through a [`MessageIndex`].
+fn indexed_start(messages: &[Message], ids: &MessageIndex, index: usize) -> usize {
+    messages[index]
+        .reference
+        .and_then(|reference| ids.position(reference.message_id))
+        .filter(|&position| position < index - 1)
+        .unwrap_or(index - 1)
+}
+
+fn main() {
+    let channel = synthetic_channel();
+    let replies: Vec<usize> = (1..channel.len())
+        .filter(|&index| channel[index].author == WHO)
+        .collect();
+
+    let start = Instant::now();
+    let ids = MessageIndex::new(&channel);
+    let build = start.elapsed();
+
+    let start = Instant::now();
+    let mut indexed_total = 0;
+    for &index in &replies {
+        indexed_total += indexed_start(&channel, &ids, index);
+    }
+    let indexed = start.elapsed();
+    black_box(indexed_total);
+
+    let start = Instant::now();
+    let mut linear_total = 0;
+    for &index in &replies {
+        linear_total += linear_start(&channel, index);
+    }
+    let linear = start.elapsed();
+    black_box(linear_total);
+    assert_eq!(
+        indexed_total, linear_total,
+        "both lookups find the same starts"
+    );
+
+    println!(
+        "{} replies in a channel of {MESSAGES} messages",
+        replies.len()
+    );
+    println!(
+        "building the index: {:>10.2}ms",
+        build.as_secs_f64() * 1000.0
+    );
+    println!(
+        "indexed lookup:     {:>10.2}ms",
+        indexed.as_secs_f64() * 1000.0
+    );
+    println!(
+        "linear lookup:      {:>10.2}ms",
+        linear.as_secs_f64() * 1000.0
+    );
+}
@@ -0,0 +1,325 @@
+use std::{num::NonZeroUsize, path::PathBuf};
+
+use chrono::TimeDelta;
+use clap::{Args, Parser, Subcommand};
+use parsediscordarchive::{
+    Charset, ContextOptions, ContextStrategy, Format, MediaMode, MentionStyle, MessageFilter,
+    NormalizeOptions, QualityFilter, RecordKind, RedactKind, SpoilerMode,
+};
+use regex::Regex;
+
+/// Turn Discord channel archives into prompt/reply datasets.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Extract prompt/reply pairs for one or more users.
+    Extract(Box<ExtractArgs>),
+    /// Print message and author counts for an archive.
+    Stats(ArchiveArgs),
+    /// Check that every message file in an archive parses.
+    Validate(ArchiveArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveArgs {
+    /// Root directory of the unpacked archive, the same packed into a `.zip`,
+    /// `.tar`, `.tar.gz` or `.tar.zst`, or a DiscordChatExporter JSON file.
+    #[arg(short, long)]
+    pub archive: PathBuf,
+    /// Abort on the first file that fails to load instead of skipping it.
+    #[arg(long)]
+    pub strict: bool,
+    /// Number of files to parse at once. Defaults to the number of CPUs.
+    #[arg(short = 'j', long)]
+    pub threads: Option<NonZeroUsize>,
+    /// Treat the archive as a Discord data package ("Request my data"). These
+    /// only hold the owner's messages, so pair with `--records monologue`.
+    #[arg(long)]
+    pub package: bool,
+    /// User id of the data package owner, if the package has no
+    /// `account/user.json`.
+    #[arg(long, requires = "package")]
+    pub owner: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExtractArgs {
+    #[command(flatten)]
+    pub archive: ArchiveArgs,
+    /// Discord user ids whose messages become replies. Each user gets their
+    /// own dataset.
+    #[arg(
+        short,
+        long = "user",
+        value_delimiter = ',',
+        required_unless_present = "all_users",
+        conflicts_with = "all_users"
+    )]
+    pub users: Vec<u64>,
+    /// Build a dataset for every author with at least `--min-messages`
+    /// messages.
+    #[arg(long)]
+    pub all_users: bool,
+    /// Authors with fewer messages than this are left out by `--all-users`.
+    #[arg(long, default_value_t = 100, requires = "all_users")]
+    pub min_messages: usize,
+    /// Where to write the dataset when extracting a single user.
+    #[arg(short, long, conflicts_with = "all_users")]
+    pub output: Option<PathBuf>,
+    /// Directory to write `prompt-<user>.<format>` datasets to.
+    #[arg(long, default_value = ".", conflicts_with = "output")]
+    pub output_dir: PathBuf,
+    /// Output layout: `jsonl` (one pair per line) or `json` (a single array).
+    #[arg(short, long, default_value_t = Format::JsonLines)]
+    pub format: Format,
+    /// What each record holds: `pairs` of prompt and reply, `conversations`
+    /// as a list of turns with speaker roles, `chatml` messages for chat
+    /// fine-tuning APIs, or `monologue` replies with no prompt.
+    #[arg(short, long, default_value_t = RecordKind::Pairs)]
+    pub records: RecordKind,
+    /// System message to start each `chatml` record with.
+    #[arg(long)]
+    pub system_prompt: Option<String>,
+    /// Follow replies into other channels, such as from a thread to its
+    /// parent channel. This holds the whole archive in memory.
+    #[arg(long)]
+    pub cross_channel: bool,
+    /// What to do with attachments, embeds and stickers: `drop` them,
+    /// write `placeholders` such as `[image: cat.png]` into the text, or keep
+    /// them as structured `fields` on each record.
+    #[arg(long, default_value_t = MediaMode::Drop)]
+    pub media: MediaMode,
+    /// What to do with `<@user>`, `<@&role>` and `<#channel>` mentions:
+    /// `keep` them, replace them with `names`, or with `names` but numbered
+    /// `pseudonyms` for users. Rewriting reads the archive twice, once to
+    /// collect names, unless `--cross-channel` is set.
+    #[arg(long, default_value_t = MentionStyle::Keep)]
+    pub mentions: MentionStyle,
+    /// Replace user ids in records and file names, and user mentions in
+    /// message text, with labels derived from `--pseudonym-key`. The same key
+    /// always gives the same labels.
+    #[arg(long, requires = "pseudonym_key")]
+    pub pseudonymize: bool,
+    /// Secret key for `--pseudonymize`.
+    #[arg(
+        long,
+        env = "PARSEDISCORDARCHIVE_PSEUDONYM_KEY",
+        hide_env_values = true
+    )]
+    pub pseudonym_key: Option<String>,
+    /// Add a `source` to each record with the guild, channel and thread it
+    /// came from.
+    #[arg(long)]
+    pub metadata: bool,
+    #[command(flatten)]
+    pub context: ContextArgs,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[command(flatten)]
+    pub quality: QualityArgs,
+    #[command(flatten)]
+    pub normalize: NormalizeArgs,
+    #[command(flatten)]
+    pub redact: RedactArgs,
+}
+
+impl ExtractArgs {
+    /// The record kind to build, with any options that only apply to it.
+    pub fn record_kind(&self) -> RecordKind {
+        match &self.records {
+            RecordKind::ChatMl { .. } => RecordKind::ChatMl {
+                system_prompt: self.system_prompt.clone(),
+            },
+            kind => kind.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ContextArgs {
+    /// Most earlier messages to include in a prompt.
+    #[arg(long, default_value_t = 5)]
+    pub context_messages: usize,
+    /// Earlier messages more than this many minutes before the reply are left
+    /// out of the prompt.
+    #[arg(long, default_value = "10", value_name = "MINUTES", value_parser = parse_minutes)]
+    pub context_minutes: TimeDelta,
+    /// Most characters a prompt may contain.
+    #[arg(long)]
+    pub context_chars: Option<usize>,
+    /// Use the chain of replies leading to each message as its context,
+    /// instead of the messages just before it.
+    #[arg(long, conflicts_with = "thread_starter")]
+    pub reply_chain: bool,
+    /// Most replies to follow back with `--reply-chain`.
+    #[arg(long, default_value_t = 8, requires = "reply_chain")]
+    pub reply_chain_depth: usize,
+    /// In threads and forum posts, always start the context with the post
+    /// that opened the thread. It counts towards `--context-messages`, and is
+    /// left out for replies to messages in other channels.
+    #[arg(long)]
+    pub thread_starter: bool,
+    /// Head the opening post with the thread's title, with `--thread-starter`.
+    #[arg(long, requires = "thread_starter")]
+    pub thread_title: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FilterArgs {
+    /// Leave out messages sent by bot accounts.
+    #[arg(long)]
+    pub skip_bots: bool,
+    /// Leave out messages sent through webhooks.
+    #[arg(long)]
+    pub skip_webhooks: bool,
+    /// Keep join notices, pins, slash command responses and other system
+    /// messages, which are left out by default.
+    #[arg(long)]
+    pub keep_system: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct QualityArgs {
+    /// Skip replies shorter than this many characters. They can still be
+    /// context for other replies.
+    #[arg(long)]
+    pub min_reply_chars: Option<usize>,
+    /// Skip replies longer than this many characters.
+    #[arg(long)]
+    pub max_reply_chars: Option<usize>,
+    /// Skip replies with fewer than this many words.
+    #[arg(long)]
+    pub min_reply_words: Option<usize>,
+    /// Skip replies that are nothing but links.
+    #[arg(long)]
+    pub skip_link_only: bool,
+    /// Skip replies that are nothing but emoji.
+    #[arg(long)]
+    pub skip_emoji_only: bool,
+    /// Skip replies mostly written in another charset: `ascii`, `latin`,
+    /// `cyrillic` or `cjk`.
+    #[arg(long)]
+    pub charset: Option<Charset>,
+    /// Share of a reply's letters that must be in `--charset`, from 0 to 1.
+    #[arg(long, default_value_t = 0.8, requires = "charset")]
+    pub charset_share: f64,
+    /// Only keep replies matching one of these regular expressions.
+    #[arg(long = "allow", value_name = "REGEX")]
+    pub allow: Vec<Regex>,
+    /// Skip replies matching any of these regular expressions.
+    #[arg(long = "deny", value_name = "REGEX")]
+    pub deny: Vec<Regex>,
+}
+
+impl From<&QualityArgs> for QualityFilter {
+    fn from(args: &QualityArgs) -> Self {
+        Self {
+            min_chars: args.min_reply_chars,
+            max_chars: args.max_reply_chars,
+            min_words: args.min_reply_words,
+            link_only: args.skip_link_only,
+            emoji_only: args.skip_emoji_only,
+            charset: args.charset,
+            min_charset_share: args.charset_share,
+            allow: args.allow.clone(),
+            deny: args.deny.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct NormalizeArgs {
+    /// Write custom emoji such as `<:pepe:1234>` as `:pepe:`.
+    #[arg(long)]
+    pub emoji_names: bool,
+    /// Write `<t:1700000000:R>` timestamps as ISO 8601 dates.
+    #[arg(long)]
+    pub iso_timestamps: bool,
+    /// What to do with `||spoilers||`: `keep` them, `unwrap` them to plain
+    /// text, or `hide` them behind `[spoiler]`.
+    #[arg(long, default_value_t = SpoilerMode::Keep)]
+    pub spoilers: SpoilerMode,
+    /// Remove markdown formatting such as `**bold**` and `# headings`. Code
+    /// blocks are never changed.
+    #[arg(long)]
+    pub strip_markdown: bool,
+}
+
+impl From<&NormalizeArgs> for NormalizeOptions {
+    fn from(args: &NormalizeArgs) -> Self {
+        Self {
+            emoji: args.emoji_names,
+            timestamps: args.iso_timestamps,
+            spoilers: args.spoilers,
+            strip_markdown: args.strip_markdown,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RedactArgs {
+    /// Replace these in message text with placeholders such as `[EMAIL]`:
+    /// `token`, `invite`, `email`, `ip` or `phone`.
+    #[arg(long, value_delimiter = ',', conflicts_with = "redact_all")]
+    pub redact: Vec<RedactKind>,
+    /// Redact everything `--redact` knows about.
+    #[arg(long)]
+    pub redact_all: bool,
+    /// Write how many of each kind were redacted from each file to this CSV.
+    #[arg(long)]
+    pub redaction_report: Option<PathBuf>,
+}
+
+impl RedactArgs {
+    pub fn kinds(&self) -> Vec<RedactKind> {
+        if self.redact_all {
+            RedactKind::ALL.to_vec()
+        } else {
+            self.redact.clone()
+        }
+    }
+}
+
+impl From<&FilterArgs> for MessageFilter {
+    fn from(args: &FilterArgs) -> Self {
+        Self {
+            bots: args.skip_bots,
+            webhooks: args.skip_webhooks,
+            system: !args.keep_system,
+        }
+    }
+}
+
+impl From<&ContextArgs> for ContextOptions {
+    fn from(args: &ContextArgs) -> Self {
+        let strategy = if args.reply_chain {
+            ContextStrategy::ReplyChain {
+                max_depth: args.reply_chain_depth,
+            }
+        } else if args.thread_starter {
+            ContextStrategy::ThreadStarter {
+                include_title: args.thread_title,
+            }
+        } else {
+            ContextStrategy::Recent
+        };
+        Self {
+            strategy,
+            max_messages: args.context_messages,
+            max_gap: args.context_minutes,
+            max_chars: args.context_chars,
+        }
+    }
+}
+
+/// Parse a whole number of minutes, which can't be negative.
+fn parse_minutes(s: &str) -> Result<TimeDelta, String> {
+    let minutes = s.parse::<u32>().map_err(|e| e.to_string())?;
+    TimeDelta::try_minutes(minutes.into()).ok_or_else(|| format!("{minutes} minutes is too long"))
+}
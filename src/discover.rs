@@ -0,0 +1,117 @@
+//! Locating message files inside an unpacked archive.
+
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+pub(crate) fn walkdir(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let read_dir_error = |source| Error::ReadDir {
+        path: path.to_owned(),
+        source,
+    };
+    path.read_dir()
+        .map_err(read_dir_error)?
+        .map(|v| v.map(|v| v.path()).map_err(read_dir_error))
+        .collect()
+}
+
+/// Extensions a messages file may carry after `.json` when it has been
+/// compressed on its own.
+const COMPRESSED_EXTENSIONS: [&str; 2] = ["gz", "zst"];
+
+/// Whether `name` is `file_name` itself or a compressed copy of it, like
+/// `channel_messages.json.gz`.
+pub(crate) fn is_named(name: &str, file_name: &str) -> bool {
+    name.strip_prefix(file_name).is_some_and(|rest| {
+        rest.is_empty()
+            || rest
+                .strip_prefix('.')
+                .is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext))
+    })
+}
+
+/// The file called `file_name` in `dir`, or a compressed copy of it.
+fn find_file(dir: &Path, file_name: &str) -> Option<PathBuf> {
+    let plain = dir.join(file_name);
+    if plain.exists() {
+        return Some(plain);
+    }
+    COMPRESSED_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{file_name}.{ext}")))
+        .find(|path| path.exists())
+}
+
+/// Find every `channel_messages.json` and `threads/*/thread_messages.json`
+/// below `root_path`. Channel files come first, followed by thread files.
+/// Each may also be compressed on its own, as `.json.gz` or `.json.zst`.
+///
+/// DiscordChatExporter archives are a flat directory of `.json` files, one
+/// per channel; any `.json` files directly inside `root_path` are included
+/// first. `root_path` may also be a single such file.
+///
+/// Fails if `root_path` or any `threads` directory cannot be listed, see
+/// [`find_channel_files`] to skip the threads instead.
+pub fn channel_files(root_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut skipped = Vec::new();
+    let files = find_channel_files(root_path, &mut skipped)?;
+    match skipped.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(files),
+    }
+}
+
+/// Like [`channel_files`], but a `threads` directory that cannot be listed is
+/// left out and its error added to `skipped`. Only fails if `root_path`
+/// itself cannot be listed.
+pub fn find_channel_files(
+    root_path: &Path,
+    skipped: &mut Vec<Error>,
+) -> Result<Vec<PathBuf>, Error> {
+    if root_path.is_file() {
+        return Ok(vec![root_path.to_owned()]);
+    }
+    let (mut files, mut channel_dirs): (Vec<PathBuf>, Vec<PathBuf>) =
+        walkdir(root_path)?.into_iter().partition(|v| {
+            v.is_file()
+                && v.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.rsplit_once(".json"))
+                    .is_some_and(|(_, rest)| is_named(rest, ""))
+        });
+    files.sort();
+    channel_dirs.retain(|v| v.is_dir());
+    let mut thread_dirs = Vec::with_capacity(1024);
+
+    for dir in &channel_dirs {
+        let threads_dir = dir.join("threads");
+        if threads_dir.exists() {
+            match walkdir(&threads_dir) {
+                Ok(mut dirs) => thread_dirs.append(&mut dirs),
+                Err(e) => {
+                    eprintln!("{e}, skipping..");
+                    skipped.push(e);
+                }
+            }
+        } else {
+            eprintln!("Found no threads in {dir:?}, skipping..");
+        }
+    }
+
+    for dir in &channel_dirs {
+        if let Some(messages_path) = find_file(dir, "channel_messages.json") {
+            files.push(messages_path);
+        } else {
+            eprintln!("Found no channel_messages.json in {dir:?}, skipping..");
+        }
+    }
+
+    for dir in thread_dirs {
+        if let Some(messages_path) = find_file(&dir, "thread_messages.json") {
+            files.push(messages_path);
+        } else {
+            eprintln!("Found no thread_messages.json in {dir:?}, skipping..");
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,78 @@
+//! The error type shared by discovery, loading and output.
+
+use std::{fmt, io, path::PathBuf};
+
+/// Something went wrong with a particular file or directory.
+#[derive(Debug)]
+pub enum Error {
+    /// A directory could not be listed.
+    ReadDir { path: PathBuf, source: io::Error },
+    /// A messages file could not be opened.
+    Open { path: PathBuf, source: io::Error },
+    /// A gzip or zstd compressed messages file could not be decompressed.
+    Decompress { path: PathBuf, source: io::Error },
+    /// A messages file was not valid JSON of the expected shape.
+    Parse {
+        path: PathBuf,
+        source: simd_json::Error,
+    },
+    /// A file inside a `.zip` or tarball could not be read.
+    ReadArchive { path: PathBuf, source: io::Error },
+    /// A data package `messages.csv` was malformed.
+    ParseCsv { path: PathBuf, source: csv::Error },
+    /// The owner of a data package could not be determined.
+    UnknownOwner { path: PathBuf },
+    /// The output file could not be created or written.
+    Output { path: PathBuf, source: io::Error },
+}
+
+impl Error {
+    /// The file or directory this error is about.
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            Self::ReadDir { path, .. }
+            | Self::Open { path, .. }
+            | Self::Decompress { path, .. }
+            | Self::Parse { path, .. }
+            | Self::ReadArchive { path, .. }
+            | Self::ParseCsv { path, .. }
+            | Self::UnknownOwner { path }
+            | Self::Output { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadDir { path, source } => write!(f, "failed to list {path:?}: {source}"),
+            Self::Open { path, source } => write!(f, "failed to open {path:?}: {source}"),
+            Self::Decompress { path, source } => {
+                write!(f, "failed to decompress {path:?}: {source}")
+            }
+            Self::Parse { path, source } => write!(f, "failed to parse {path:?}: {source}"),
+            Self::ReadArchive { path, source } => write!(f, "failed to read {path:?}: {source}"),
+            Self::ParseCsv { path, source } => write!(f, "failed to parse {path:?}: {source}"),
+            Self::UnknownOwner { path } => write!(
+                f,
+                "could not determine the owner of the data package at {path:?}, it has no account/user.json"
+            ),
+            Self::Output { path, source } => write!(f, "failed to write {path:?}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ReadDir { source, .. }
+            | Self::Open { source, .. }
+            | Self::ReadArchive { source, .. }
+            | Self::Decompress { source, .. }
+            | Self::Output { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source),
+            Self::ParseCsv { source, .. } => Some(source),
+            Self::UnknownOwner { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Types for archives produced by
+//! [DiscordChatExporter](https://github.com/Tyrrrz/DiscordChatExporter), which
+//! writes one JSON file per channel with a header describing the guild and
+//! channel followed by its messages.
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::{
+    meta::ChannelInfo,
+    model::{Attachment, Embed, Media, Message, Names, Reference, Sticker},
+};
+
+/// A whole DiscordChatExporter JSON file.
+#[derive(Debug, Deserialize)]
+pub struct ExportFile {
+    pub guild: ExportGuild,
+    pub channel: ExportChannel,
+    pub messages: Vec<ExportMessage>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct ExportGuild {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    pub name: String,
+}
+
+impl ExportFile {
+    /// Describe the exported channel. Threads are exported with their parent
+    /// channel as the category.
+    pub fn info(&self) -> ChannelInfo {
+        let channel = &self.channel;
+        let (channel_id, channel_name, thread_id, thread_name) = if channel.is_thread() {
+            (
+                channel.category_id,
+                channel.category.clone(),
+                Some(channel.id),
+                Some(channel.name.clone()),
+            )
+        } else {
+            (Some(channel.id), Some(channel.name.clone()), None, None)
+        };
+        ChannelInfo {
+            guild_id: Some(self.guild.id),
+            channel_id,
+            channel_name,
+            thread_id,
+            thread_name,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportChannel {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    /// Such as `GuildTextChat` or `GuildPublicThread`.
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    pub name: String,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub category_id: Option<u64>,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl ExportChannel {
+    pub fn is_thread(&self) -> bool {
+        self.kind.ends_with("Thread")
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct ExportMessage {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    pub content: String,
+    pub timestamp: chrono::DateTime<Utc>,
+    pub author: ExportAuthor,
+    /// `Default` or `Reply` for ordinary messages, anything else is a system
+    /// message.
+    #[serde(rename = "type", default = "default_kind")]
+    pub kind: String,
+    #[serde(default)]
+    pub reference: Option<ExportReference>,
+    #[serde(default)]
+    pub mentions: Vec<ExportAuthor>,
+    #[serde(default)]
+    pub attachments: Vec<ExportAttachment>,
+    #[serde(default)]
+    pub embeds: Vec<Embed>,
+    #[serde(default)]
+    pub stickers: Vec<Sticker>,
+}
+
+fn default_kind() -> String {
+    "Default".to_owned()
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportAuthor {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    #[serde(default)]
+    pub is_bot: bool,
+    /// The username.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The author's roles in the guild. Only present for authors, not for
+    /// mentioned users.
+    #[serde(default)]
+    pub roles: Vec<ExportRole>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct ExportRole {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportAttachment {
+    pub url: Option<String>,
+    pub file_name: String,
+    #[serde(default)]
+    pub file_size_bytes: Option<u64>,
+}
+
+impl From<ExportAttachment> for Attachment {
+    fn from(v: ExportAttachment) -> Self {
+        Self {
+            filename: v.file_name,
+            url: v.url,
+            content_type: None,
+            size: v.file_size_bytes,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReference {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub message_id: Option<u64>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub guild_id: Option<u64>,
+}
+
+impl From<ExportMessage> for Message {
+    fn from(mut v: ExportMessage) -> Self {
+        let roles = std::mem::take(&mut v.author.roles);
+        Self {
+            id: v.id,
+            author: v.author.id,
+            content: v.content,
+            timestamp: v.timestamp,
+            reference: v.reference.and_then(|v| {
+                Some(Reference {
+                    message_id: v.message_id?,
+                    channel_id: v.channel_id,
+                    guild_id: v.guild_id,
+                })
+            }),
+            media: Media {
+                attachments: v.attachments.into_iter().map(Attachment::from).collect(),
+                embeds: v.embeds,
+                stickers: v.stickers,
+            },
+            bot: v.author.is_bot,
+            // Exports don't say whether a message came from a webhook.
+            webhook: false,
+            system: !matches!(v.kind.as_str(), "Default" | "Reply"),
+            names: Names {
+                roles: roles.into_iter().map(|v| (v.id, v.name)).collect(),
+                users: [v.author]
+                    .into_iter()
+                    .chain(v.mentions)
+                    .filter_map(|v| Some((v.id, v.name?)))
+                    .collect(),
+            },
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! Leaving out messages and replies that a dataset shouldn't learn from.
+
+use std::{fmt, str::FromStr};
+
+use regex::Regex;
+
+use crate::model::Message;
+
+/// Which messages to remove from a channel before pairing. Removed messages
+/// appear in neither prompts nor replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageFilter {
+    /// Messages by bot accounts.
+    pub bots: bool,
+    /// Messages sent through webhooks.
+    pub webhooks: bool,
+    /// Join notices, pins, slash command responses and other messages that
+    /// are neither ordinary messages nor replies.
+    pub system: bool,
+}
+
+impl Default for MessageFilter {
+    fn default() -> Self {
+        Self {
+            bots: false,
+            webhooks: false,
+            system: true,
+        }
+    }
+}
+
+impl MessageFilter {
+    /// Whether `message` should be removed.
+    pub fn excludes(&self, message: &Message) -> bool {
+        (self.bots && message.bot)
+            || (self.webhooks && message.webhook)
+            || (self.system && message.system)
+    }
+
+    /// Remove every excluded message from a channel, returning how many were
+    /// removed.
+    pub fn apply(&self, messages: &mut Vec<Message>) -> usize {
+        let before = messages.len();
+        messages.retain(|v| !self.excludes(v));
+        before - messages.len()
+    }
+}
+
+/// A reason [`QualityFilter`] rejected a reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QualityCheck {
+    TooShort,
+    TooLong,
+    TooFewWords,
+    LinkOnly,
+    EmojiOnly,
+    Charset,
+    /// Matched none of the allow patterns.
+    NotAllowed,
+    /// Matched a deny pattern.
+    Denied,
+}
+
+impl QualityCheck {
+    /// Every check, in the order they are applied.
+    pub const ALL: [Self; 8] = [
+        Self::TooShort,
+        Self::TooLong,
+        Self::TooFewWords,
+        Self::LinkOnly,
+        Self::EmojiOnly,
+        Self::Charset,
+        Self::NotAllowed,
+        Self::Denied,
+    ];
+}
+
+impl fmt::Display for QualityCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TooShort => "too short",
+            Self::TooLong => "too long",
+            Self::TooFewWords => "too few words",
+            Self::LinkOnly => "link only",
+            Self::EmojiOnly => "emoji only",
+            Self::Charset => "wrong charset",
+            Self::NotAllowed => "not allowed",
+            Self::Denied => "denied",
+        })
+    }
+}
+
+/// The writing systems [`QualityFilter`] can require replies to be in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Unaccented `a` to `z`.
+    Ascii,
+    /// Latin letters, including accented ones.
+    Latin,
+    Cyrillic,
+    /// Chinese, Japanese and Korean.
+    Cjk,
+}
+
+impl Charset {
+    fn contains(self, c: char) -> bool {
+        match self {
+            Self::Ascii => c.is_ascii_alphabetic(),
+            Self::Latin => {
+                c.is_ascii_alphabetic()
+                    || matches!(c, '\u{c0}'..='\u{24f}' | '\u{1e00}'..='\u{1eff}')
+                        && c != '\u{d7}'
+                        && c != '\u{f7}'
+            }
+            Self::Cyrillic => matches!(c, '\u{400}'..='\u{52f}'),
+            Self::Cjk => matches!(
+                c,
+                '\u{1100}'..='\u{11ff}'
+                    | '\u{3040}'..='\u{30ff}'
+                    | '\u{3400}'..='\u{4dbf}'
+                    | '\u{4e00}'..='\u{9fff}'
+                    | '\u{ac00}'..='\u{d7af}'
+            ),
+        }
+    }
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Self::Ascii),
+            "latin" => Ok(Self::Latin),
+            "cyrillic" => Ok(Self::Cyrillic),
+            "cjk" => Ok(Self::Cjk),
+            _ => Err(format!(
+                "unknown charset {s:?}, expected one of: ascii, latin, cyrillic, cjk"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ascii => "ascii",
+            Self::Latin => "latin",
+            Self::Cyrillic => "cyrillic",
+            Self::Cjk => "cjk",
+        })
+    }
+}
+
+/// Rules a reply has to pass to make it into a dataset. Unlike
+/// [`MessageFilter`], a rejected reply is still used as context for others.
+#[derive(Debug, Clone)]
+pub struct QualityFilter {
+    /// Fewest characters a reply may have.
+    pub min_chars: Option<usize>,
+    /// Most characters a reply may have.
+    pub max_chars: Option<usize>,
+    /// Fewest whitespace separated words a reply may have.
+    pub min_words: Option<usize>,
+    /// Reject replies that are nothing but links.
+    pub link_only: bool,
+    /// Reject replies that are nothing but emoji.
+    pub emoji_only: bool,
+    /// Reject replies where fewer than `min_charset_share` of the letters
+    /// are in this charset. Replies with no letters pass.
+    pub charset: Option<Charset>,
+    pub min_charset_share: f64,
+    /// If any are given, replies must match at least one.
+    pub allow: Vec<Regex>,
+    /// Replies must match none of these.
+    pub deny: Vec<Regex>,
+}
+
+impl Default for QualityFilter {
+    fn default() -> Self {
+        Self {
+            min_chars: None,
+            max_chars: None,
+            min_words: None,
+            link_only: false,
+            emoji_only: false,
+            charset: None,
+            min_charset_share: 0.8,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl QualityFilter {
+    /// Check a reply, returning the first check it fails.
+    pub fn check(&self, reply: &str) -> Result<(), QualityCheck> {
+        let chars = reply.chars().count();
+        if self.min_chars.is_some_and(|min| chars < min) {
+            return Err(QualityCheck::TooShort);
+        }
+        if self.max_chars.is_some_and(|max| chars > max) {
+            return Err(QualityCheck::TooLong);
+        }
+        if self
+            .min_words
+            .is_some_and(|min| reply.split_whitespace().count() < min)
+        {
+            return Err(QualityCheck::TooFewWords);
+        }
+        if self.link_only && is_link_only(reply) {
+            return Err(QualityCheck::LinkOnly);
+        }
+        if self.emoji_only && is_emoji_only(reply) {
+            return Err(QualityCheck::EmojiOnly);
+        }
+        if let Some(charset) = self.charset {
+            let letters = reply.chars().filter(|c| c.is_alphabetic());
+            let (total, matching) = letters.fold((0, 0), |(total, matching), c| {
+                (total + 1, matching + usize::from(charset.contains(c)))
+            });
+            if total > 0 && (matching as f64) < total as f64 * self.min_charset_share {
+                return Err(QualityCheck::Charset);
+            }
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|v| v.is_match(reply)) {
+            return Err(QualityCheck::NotAllowed);
+        }
+        if self.deny.iter().any(|v| v.is_match(reply)) {
+            return Err(QualityCheck::Denied);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `text` has at least one link and nothing else but whitespace and
+/// punctuation.
+fn is_link_only(text: &str) -> bool {
+    let mut any = false;
+    for word in text.split_whitespace() {
+        let word = word.trim_start_matches('<').trim_end_matches('>');
+        if word.starts_with("https://") || word.starts_with("http://") {
+            any = true;
+        } else if word.chars().any(char::is_alphanumeric) {
+            return false;
+        }
+    }
+    any
+}
+
+/// Whether `text` is made up only of emoji: unicode emoji, custom
+/// `<:name:id>` emoji and `:name:` shortcodes.
+fn is_emoji_only(text: &str) -> bool {
+    let is_name = |v: &str| !v.is_empty() && v.chars().all(|c| c.is_alphanumeric() || c == '_');
+    let mut any = false;
+    for word in text.split_whitespace() {
+        let custom = word
+            .strip_prefix("<a:")
+            .or_else(|| word.strip_prefix("<:"))
+            .and_then(|v| v.strip_suffix('>'))
+            .and_then(|v| v.split_once(':'))
+            .is_some_and(|(name, id)| {
+                is_name(name) && !id.is_empty() && id.bytes().all(|v| v.is_ascii_digit())
+            });
+        let shortcode = word
+            .strip_prefix(':')
+            .and_then(|v| v.strip_suffix(':'))
+            .is_some_and(is_name);
+        let unicode =
+            word.chars().any(is_emoji) && word.chars().all(|c| is_emoji(c) || is_emoji_modifier(c));
+        if !(custom || shortcode || unicode) {
+            return false;
+        }
+        any = true;
+    }
+    any
+}
+
+/// Whether `c` is a pictograph, symbol or flag letter that renders as emoji,
+/// as opposed to punctuation such as `…` or `¿`.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{a9}'
+            | '\u{ae}'
+            | '\u{203c}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2300}'..='\u{23ff}'
+            | '\u{25aa}'..='\u{25fe}'
+            | '\u{2600}'..='\u{27bf}'
+            | '\u{2b00}'..='\u{2bff}'
+            | '\u{3030}'
+            | '\u{303d}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1f000}'..='\u{1faff}'
+    )
+}
+
+/// Whether `c` only joins or modifies emoji around it: zero width joiners,
+/// variation selectors, keycaps and flag tags.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200d}' | '\u{fe0e}' | '\u{fe0f}' | '\u{20e3}' | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// How many replies each [`QualityCheck`] rejected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QualityReport {
+    counts: [usize; QualityCheck::ALL.len()],
+}
+
+impl QualityReport {
+    pub fn add(&mut self, check: QualityCheck) {
+        self.counts[check as usize] += 1;
+    }
+
+    pub fn get(&self, check: QualityCheck) -> usize {
+        self.counts[check as usize]
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Each check that rejected at least one reply, and its count.
+    pub fn iter(&self) -> impl Iterator<Item = (QualityCheck, usize)> + '_ {
+        QualityCheck::ALL
+            .into_iter()
+            .map(|check| (check, self.get(check)))
+            .filter(|(_, count)| *count > 0)
+    }
+}
+
+impl fmt::Display for QualityReport {
+    /// Such as `12 too short, 3 link only`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (check, count)) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{count} {check}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_and_words() {
+        let filter = QualityFilter {
+            min_chars: Some(3),
+            max_chars: Some(10),
+            min_words: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(filter.check("ok"), Err(QualityCheck::TooShort));
+        assert_eq!(filter.check("much too long"), Err(QualityCheck::TooLong));
+        assert_eq!(filter.check("lol"), Err(QualityCheck::TooFewWords));
+        assert_eq!(filter.check("ok then"), Ok(()));
+        // Characters, not bytes.
+        assert_eq!(filter.check("ça va très"), Ok(()));
+    }
+
+    #[test]
+    fn link_only() {
+        let filter = QualityFilter {
+            link_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.check("https://example.com/a"),
+            Err(QualityCheck::LinkOnly)
+        );
+        assert_eq!(
+            filter.check("<https://example.com> http://example.org !"),
+            Err(QualityCheck::LinkOnly)
+        );
+        assert_eq!(filter.check("look https://example.com"), Ok(()));
+        assert_eq!(filter.check("!!"), Ok(()));
+    }
+
+    #[test]
+    fn emoji_only() {
+        let filter = QualityFilter {
+            emoji_only: true,
+            ..Default::default()
+        };
+        for reply in [
+            "😂",
+            "😂😂 🔥",
+            "<:pepe:1234> <a:dance:5678>",
+            ":pepe: 👍🏽",
+            "👨‍👩‍👧 ❤️ 🇫🇷",
+        ] {
+            assert_eq!(
+                filter.check(reply),
+                Err(QualityCheck::EmojiOnly),
+                "{reply:?}"
+            );
+        }
+        for reply in [
+            "…",
+            "— ¿",
+            "lol 😂",
+            "<t:1700000000:R>",
+            "<@1234>",
+            "::",
+            "ok",
+        ] {
+            assert_eq!(filter.check(reply), Ok(()), "{reply:?}");
+        }
+    }
+
+    #[test]
+    fn charset() {
+        let filter = QualityFilter {
+            charset: Some(Charset::Latin),
+            ..Default::default()
+        };
+        assert_eq!(filter.check("crème brûlée, s'il vous plaît"), Ok(()));
+        assert_eq!(filter.check("Привет как дела"), Err(QualityCheck::Charset));
+        // Mostly Latin is enough, and text with no letters passes.
+        assert_eq!(filter.check("that was so very funny, да"), Ok(()));
+        assert_eq!(filter.check("123 😂"), Ok(()));
+
+        let ascii = QualityFilter {
+            charset: Some(Charset::Ascii),
+            min_charset_share: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(ascii.check("café"), Err(QualityCheck::Charset));
+        let cjk = QualityFilter {
+            charset: Some(Charset::Cjk),
+            ..Default::default()
+        };
+        assert_eq!(cjk.check("こんにちは世界"), Ok(()));
+        assert_eq!(cjk.check("hello"), Err(QualityCheck::Charset));
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let filter = QualityFilter {
+            allow: vec![Regex::new("(?i)rust").unwrap()],
+            deny: vec![Regex::new(r"(?i)\bfree nitro\b").unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(filter.check("I like Rust"), Ok(()));
+        assert_eq!(filter.check("I like Go"), Err(QualityCheck::NotAllowed));
+        assert_eq!(
+            filter.check("rust and FREE NITRO"),
+            Err(QualityCheck::Denied)
+        );
+    }
+
+    #[test]
+    fn first_failing_check_is_reported() {
+        let filter = QualityFilter {
+            min_words: Some(2),
+            link_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.check("https://example.com"),
+            Err(QualityCheck::TooFewWords)
+        );
+    }
+
+    #[test]
+    fn report() {
+        let mut report = QualityReport::default();
+        assert_eq!(report.to_string(), "");
+        report.add(QualityCheck::LinkOnly);
+        report.add(QualityCheck::TooShort);
+        report.add(QualityCheck::TooShort);
+        assert_eq!(report.total(), 3);
+        assert_eq!(report.to_string(), "2 too short, 1 link only");
+    }
+
+    #[test]
+    fn charset_names_round_trip() {
+        for charset in [
+            Charset::Ascii,
+            Charset::Latin,
+            Charset::Cyrillic,
+            Charset::Cjk,
+        ] {
+            assert_eq!(charset.to_string().parse::<Charset>(), Ok(charset));
+        }
+        assert!("klingon".parse::<Charset>().is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! Turn a Discord channel archive into prompt/reply pairs for a given user.
+//!
+//! The archive is expected to be laid out as one directory per channel, each
+//! containing a `channel_messages.json` and optionally a `threads` directory
+//! holding one directory per thread with a `thread_messages.json`.
+//! DiscordChatExporter JSON files are also supported, see [`exporter`], as
+//! are Discord's own data packages, see [`package`]. Archives can also be
+//! read straight from a `.zip` or tarball, see [`packed`].
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let who = 123;
+//! let options = parsediscordarchive::ContextOptions::default();
+//! for file in parsediscordarchive::channel_files(Path::new("archive"))? {
+//!     let channel = parsediscordarchive::load_channel(&file)?;
+//!     let replies = parsediscordarchive::channel_replies(&channel.messages, who, &options);
+//!     println!("{} replies in {file:?}", replies.len());
+//! }
+//! # Ok::<(), parsediscordarchive::Error>(())
+//! ```
+
+pub mod discover;
+mod error;
+pub mod exporter;
+pub mod filter;
+pub mod load;
+pub mod media;
+pub mod mentions;
+pub mod meta;
+pub mod model;
+pub mod normalize;
+pub mod output;
+pub mod package;
+pub mod packed;
+pub mod prompt;
+pub mod pseudonym;
+pub mod redact;
+
+pub use discover::{channel_files, find_channel_files};
+pub use error::Error;
+pub use filter::{Charset, MessageFilter, QualityCheck, QualityFilter, QualityReport};
+pub use load::{
+    for_each_channel, load_channel, load_channels, parse_channel, LoadResult, Progress,
+};
+pub use media::{apply_media, MediaMode};
+pub use mentions::{MentionStyle, NameTable};
+pub use meta::ChannelInfo;
+pub use model::{
+    Attachment, AuthorId, Channel, ChatExample, ChatMessage, Conversation, DiscordMessage, Embed,
+    Entry, Media, Message, Monologue, Names, Record, Reference, Reply, Role, Sticker, Turn,
+};
+pub use normalize::{NormalizeOptions, Normalizer, SpoilerMode};
+pub use output::{DatasetWriter, Format, RecordKind};
+pub use prompt::{
+    channel_records_by, channel_replies, channel_replies_by, get_conversation, get_prompt,
+    ArchiveIndex, ContextOptions, ContextStrategy, MessageIndex,
+};
+pub use pseudonym::Pseudonymizer;
+pub use redact::{RedactKind, Redactions, Redactor};
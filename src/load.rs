@@ -0,0 +1,444 @@
+//! Reading messages files into memory.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    exporter::ExportFile,
+    meta::{layout_info, ChannelInfo},
+    model::{Channel, DiscordMessage, Message},
+    Error,
+};
+
+/// Parse one messages file, returning its messages sorted oldest first.
+///
+/// Both `channel_messages.json`/`thread_messages.json` files, which hold a
+/// bare array of messages, and DiscordChatExporter files, which wrap them in
+/// an object, are understood. The channel is described from the exporter
+/// header, or otherwise from the metadata files around it, see [`meta`].
+///
+/// Files compressed with gzip or zstd are decompressed transparently.
+///
+/// [`meta`]: crate::meta
+pub fn load_channel(path: &Path) -> Result<Channel, Error> {
+    let data = fs::read(path).map_err(|source| Error::Open {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut channel = parse_channel(path, data)?;
+    channel.info = channel.info.or(layout_info(path, |v| fs::read(v).ok()));
+    Ok(channel)
+}
+
+/// Parse the contents of a messages file that has already been read, like
+/// [`load_channel`]. `path` is only used to report errors, so only what the
+/// file itself says about the channel is filled in.
+pub fn parse_channel(path: &Path, data: Vec<u8>) -> Result<Channel, Error> {
+    let mut data = decompress(data).map_err(|source| Error::Decompress {
+        path: path.to_owned(),
+        source,
+    })?;
+    let parse_error = |source| Error::Parse {
+        path: path.to_owned(),
+        source,
+    };
+    let is_export = data.iter().find(|v| !v.is_ascii_whitespace()) == Some(&b'{');
+    let (info, mut messages): (ChannelInfo, Vec<Message>) = if is_export {
+        let export: ExportFile = simd_json::from_slice(&mut data).map_err(parse_error)?;
+        let info = export.info();
+        (
+            info,
+            export.messages.into_iter().map(Message::from).collect(),
+        )
+    } else {
+        let messages: Vec<DiscordMessage> =
+            simd_json::from_slice(&mut data).map_err(parse_error)?;
+        let messages = messages.into_iter().map(Message::from).collect();
+        (ChannelInfo::default(), messages)
+    };
+    messages.sort_by_key(|v| v.timestamp);
+    Ok(Channel { info, messages })
+}
+
+/// Decompress `data` if it starts with a gzip or zstd header, otherwise
+/// return it unchanged.
+pub fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    if data.starts_with(GZIP_MAGIC) {
+        let mut decompressed = Vec::with_capacity(data.len() * 4);
+        GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else if data.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(data.as_slice())
+    } else {
+        Ok(data)
+    }
+}
+
+/// The outcome of loading a single file.
+pub type LoadResult = Result<Channel, Error>;
+
+/// Reported by [`for_each_channel`] as files are picked up and finished.
+#[derive(Debug)]
+pub enum Progress<'a> {
+    Started {
+        /// Position of the file in the input list.
+        index: usize,
+        path: &'a Path,
+    },
+    Finished {
+        index: usize,
+        path: &'a Path,
+        /// How many files have finished so far, including this one.
+        completed: usize,
+        elapsed: Duration,
+        result: &'a LoadResult,
+    },
+}
+
+/// Load `files` with `load` across `threads` worker threads, handing each
+/// result to `consume` on the calling thread in the same order as `files`.
+///
+/// `load` is usually [`load_channel`], or
+/// [`load_package_channel`](crate::package::load_package_channel) for data
+/// packages.
+///
+/// `on_progress` is called from the worker threads. Returning
+/// [`ControlFlow::Break`] from either callback stops workers from starting any
+/// more files. Files already started are still handed to `consume`, in order,
+/// until it returns [`ControlFlow::Break`] itself, so a failure reported to
+/// `on_progress` always reaches `consume` too.
+///
+/// Workers never run more than a few files ahead of the one `consume` is
+/// waiting for, so only a bounded number of channels is held in memory at
+/// once regardless of how many files there are.
+pub fn for_each_channel<L, F, C>(
+    files: &[PathBuf],
+    load: L,
+    threads: NonZeroUsize,
+    on_progress: F,
+    mut consume: C,
+) where
+    L: Fn(&Path) -> LoadResult + Sync,
+    F: Fn(Progress) -> ControlFlow<()> + Sync,
+    C: FnMut(usize, LoadResult) -> ControlFlow<()>,
+{
+    let threads = threads.get().min(files.len()).max(1);
+    let window = threads * 2;
+    let next = AtomicUsize::new(0);
+    let completed = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    // The index of the file `consume` is waiting for.
+    let consumed = Mutex::new(0usize);
+    let caught_up = Condvar::new();
+    let (tx, rx) = mpsc::channel();
+
+    let halt = || {
+        // Held so a worker can't miss the wakeup between checking `stop` and waiting.
+        let _consumed = consumed.lock().unwrap();
+        stop.store(true, Ordering::Relaxed);
+        caught_up.notify_all();
+    };
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (next, completed, stop, consumed, caught_up) =
+                (&next, &completed, &stop, &consumed, &caught_up);
+            let (load, on_progress, halt) = (&load, &on_progress, &halt);
+            s.spawn(move || loop {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                // Once claimed, a file is always loaded and sent, even if the
+                // run stops meanwhile. Otherwise a file before the one that
+                // stopped the run could go missing, and `consume` would never
+                // be given the failure that stopped it.
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(index) else {
+                    break;
+                };
+                let guard = consumed.lock().unwrap();
+                let guard = caught_up
+                    .wait_while(guard, |consumed| {
+                        index >= *consumed + window && !stop.load(Ordering::Relaxed)
+                    })
+                    .unwrap();
+                drop(guard);
+                if on_progress(Progress::Started { index, path }).is_break() {
+                    halt();
+                }
+                let start = Instant::now();
+                let result = load(path);
+                let flow = on_progress(Progress::Finished {
+                    index,
+                    path,
+                    completed: completed.fetch_add(1, Ordering::Relaxed) + 1,
+                    elapsed: start.elapsed(),
+                    result: &result,
+                });
+                if flow.is_break() {
+                    halt();
+                }
+                if tx.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut want = 0;
+        'recv: for (index, result) in rx.iter() {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&want) {
+                if consume(want, result).is_break() {
+                    halt();
+                    break 'recv;
+                }
+                want += 1;
+                *consumed.lock().unwrap() = want;
+                caught_up.notify_all();
+            }
+        }
+    });
+}
+
+/// Load all of `files` across `threads` worker threads.
+///
+/// Like [`for_each_channel`], but collects the results. They line up with
+/// `files`, with `None` for files that were never loaded because a callback
+/// stopped the run. Every file before the one that stopped it is loaded.
+pub fn load_channels<L, F>(
+    files: &[PathBuf],
+    load: L,
+    threads: NonZeroUsize,
+    on_progress: F,
+) -> Vec<Option<LoadResult>>
+where
+    L: Fn(&Path) -> LoadResult + Sync,
+    F: Fn(Progress) -> ControlFlow<()> + Sync,
+{
+    let mut results: Vec<Option<LoadResult>> = files.iter().map(|_| None).collect();
+    for_each_channel(files, load, threads, on_progress, |index, result| {
+        results[index] = Some(result);
+        ControlFlow::Continue(())
+    });
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(count: usize) -> Vec<PathBuf> {
+        (0..count).map(|v| PathBuf::from(v.to_string())).collect()
+    }
+
+    fn index_of(path: &Path) -> usize {
+        path.to_str().unwrap().parse().unwrap()
+    }
+
+    /// Loads each file as a channel named after it, after `delay(index)`.
+    fn load_after(delay: impl Fn(usize) -> u64 + Sync) -> impl Fn(&Path) -> LoadResult + Sync {
+        move |path| {
+            thread::sleep(Duration::from_millis(delay(index_of(path))));
+            Ok(Channel {
+                info: ChannelInfo {
+                    channel_name: Some(path.to_str().unwrap().to_owned()),
+                    ..Default::default()
+                },
+                messages: Vec::new(),
+            })
+        }
+    }
+
+    fn threads(count: usize) -> NonZeroUsize {
+        NonZeroUsize::new(count).unwrap()
+    }
+
+    /// Run [`for_each_channel`], returning the indices `consume` was given.
+    fn delivered(
+        files: &[PathBuf],
+        load: impl Fn(&Path) -> LoadResult + Sync,
+        threads: NonZeroUsize,
+        on_progress: impl Fn(Progress) -> ControlFlow<()> + Sync,
+        stop_after: Option<usize>,
+    ) -> Vec<usize> {
+        let mut indices = Vec::new();
+        for_each_channel(files, load, threads, on_progress, |index, result| {
+            let channel = result.unwrap();
+            assert_eq!(channel.info.channel_name, Some(index.to_string()));
+            indices.push(index);
+            if stop_after == Some(index) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        indices
+    }
+
+    fn keep_going(_: Progress) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    #[test]
+    fn more_threads_than_files() {
+        let files = files(3);
+        let indices = delivered(&files, load_after(|_| 0), threads(8), keep_going, None);
+        assert_eq!(indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn in_order_when_loads_finish_out_of_order() {
+        let files = files(12);
+        // Earlier files take longer, so later ones finish first.
+        let load = load_after(|index| (12 - index as u64) * 3);
+        let indices = delivered(&files, load, threads(4), keep_going, None);
+        assert_eq!(indices, (0..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stopping_from_on_progress_delivers_everything_before() {
+        // Which files are lost, if any, depends on scheduling, so try a few
+        // times.
+        for k in (0..200).map(|v| v % 16) {
+            let files = files(64);
+            // Files before `k` are slow, so they are still loading when `k`
+            // stops the run.
+            let load = load_after(|index| if index < k { 1 } else { 0 });
+            let on_progress = |progress: Progress| match progress {
+                Progress::Finished { index, .. } if index == k => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            };
+            let indices = delivered(&files, load, threads(16), on_progress, None);
+            assert!(indices.len() > k, "stopped at {k}, got {indices:?}");
+            assert_eq!(indices, (0..indices.len()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn stopping_from_consume_returns() {
+        let files = files(100);
+        let load = load_after(|index| index as u64 % 3);
+        let indices = delivered(&files, load, threads(4), keep_going, Some(2));
+        assert_eq!(indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn load_channels_lines_up_with_files() {
+        let files = files(10);
+        let on_progress = |progress: Progress| match progress {
+            Progress::Finished { index: 4, .. } => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        };
+        let results = load_channels(&files, load_after(|_| 1), threads(2), on_progress);
+        assert_eq!(results.len(), 10);
+        for (index, result) in results.iter().enumerate().take(5) {
+            let channel = result.as_ref().unwrap().as_ref().unwrap();
+            assert_eq!(channel.info.channel_name, Some(index.to_string()));
+        }
+    }
+
+    /// A DiscordChatExporter file for `channel`, with one message replying to
+    /// another.
+    fn export(channel: &str) -> Vec<u8> {
+        format!(
+            r#"
+            {{
+                "guild": {{"id": "1", "name": "Guild"}},
+                "channel": {channel},
+                "messages": [
+                    {{
+                        "id": "20",
+                        "type": "Reply",
+                        "timestamp": "2024-01-01T00:01:00+00:00",
+                        "content": "hi yourself",
+                        "author": {{"id": "200", "name": "bob", "isBot": false}},
+                        "reference": {{"messageId": "10", "channelId": "5", "guildId": "1"}}
+                    }},
+                    {{
+                        "id": "10",
+                        "type": "Default",
+                        "timestamp": "2024-01-01T00:00:00+00:00",
+                        "content": "hi",
+                        "author": {{"id": "100", "name": "alice", "isBot": false}}
+                    }}
+                ]
+            }}"#
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn exporter_text_channel() {
+        let data = export(
+            r#"{"id": "5", "type": "GuildTextChat", "categoryId": "3",
+                "category": "Text Channels", "name": "general"}"#,
+        );
+        let channel = parse_channel(Path::new("general.json"), data).unwrap();
+        assert_eq!(
+            channel.info,
+            ChannelInfo {
+                guild_id: Some(1),
+                channel_id: Some(5),
+                channel_name: Some("general".to_owned()),
+                thread_id: None,
+                thread_name: None,
+            }
+        );
+        let ids: Vec<_> = channel.messages.iter().map(|v| v.id).collect();
+        assert_eq!(ids, [10, 20]);
+        let reference = channel.messages[1].reference.as_ref().unwrap();
+        assert_eq!(reference.message_id, 10);
+        assert_eq!(reference.channel_id, Some(5));
+    }
+
+    #[test]
+    fn exporter_thread_takes_category_as_parent() {
+        let data = export(
+            r#"{"id": "7", "type": "GuildPublicThread", "categoryId": "5",
+                "category": "general", "name": "weekend plans"}"#,
+        );
+        let channel = parse_channel(Path::new("weekend plans.json"), data).unwrap();
+        assert!(channel.info.is_thread());
+        assert_eq!(
+            channel.info,
+            ChannelInfo {
+                guild_id: Some(1),
+                channel_id: Some(5),
+                channel_name: Some("general".to_owned()),
+                thread_id: Some(7),
+                thread_name: Some("weekend plans".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn bare_arrays_are_not_exports() {
+        let data = br#"
+            [{"id": "10", "timestamp": "2024-01-01T00:00:00+00:00",
+              "content": "hi", "author": {"id": "100"}}]"#
+            .to_vec();
+        let channel = parse_channel(Path::new("channel_messages.json"), data).unwrap();
+        assert_eq!(channel.info, ChannelInfo::default());
+        assert_eq!(channel.messages.len(), 1);
+    }
+}
@@ -1,200 +1,421 @@
-use std::{fs::OpenOptions, path::PathBuf, time::Instant};
+use std::{
+    collections::HashMap,
+    fs,
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::Instant,
+};
 
-use chrono::Utc;
-use serde::Deserialize;
-use serde_with::{serde_as, DisplayFromStr};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use parsediscordarchive::{
+    apply_media, channel_records_by, find_channel_files, for_each_channel, load_channel,
+    load_channels,
+    package::{load_package_channel, package_files, package_owner},
+    packed::{PackedArchive, PackedKind},
+    ArchiveIndex, Channel, ContextOptions, Entry, Error, LoadResult, MentionStyle, Message,
+    MessageFilter, NameTable, NormalizeOptions, Normalizer, Progress, Pseudonymizer, QualityFilter,
+    QualityReport, Redactions, Redactor,
+};
 
-#[derive(serde::Serialize, Clone)]
-struct Reply {
-    prompt: String,
-    reply: String,
-}
+use crate::{
+    cli::{ArchiveArgs, Cli, Command, ExtractArgs},
+    outputs::{file_name, label, Outputs, RedactionReport, Selection},
+};
 
-#[derive(serde::Serialize, Clone)]
-struct Message {
-    id: u64,
-    content: String,
-    timestamp: chrono::DateTime<Utc>,
-    author: u64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    reference: Option<u64>,
-}
+mod cli;
+mod outputs;
 
-#[serde_as]
-#[derive(serde::Deserialize)]
-struct DiscordMessage {
-    #[serde_as(as = "DisplayFromStr")]
-    id: u64,
-    content: String,
-    timestamp: chrono::DateTime<Utc>,
-    author: DiscordAuthor,
-    message_reference: Option<DiscordMessageReference>,
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Extract(args) => extract(*args),
+        Command::Stats(args) => stats(args),
+        Command::Validate(args) => validate(args),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
-#[serde_as]
-#[derive(Debug, Deserialize)]
-struct DiscordAuthor {
-    #[serde_as(as = "DisplayFromStr")]
-    id: u64,
-}
+fn extract(args: ExtractArgs) -> Result<ExitCode, Error> {
+    let context = ContextOptions::from(&args.context);
+    let kind = args.record_kind();
+    let filter = MessageFilter::from(&args.filter);
+    let normalizer = Normalizer::new(NormalizeOptions::from(&args.normalize));
+    let mut filtered = 0;
+    let quality = QualityFilter::from(&args.quality);
+    let mut dropped = QualityReport::default();
+    let pseudonymizer = args
+        .pseudonym_key
+        .as_ref()
+        .filter(|_| args.pseudonymize)
+        .map(|key| Pseudonymizer::new(key.as_bytes()));
+    let selection = if args.all_users {
+        Selection::All {
+            min_messages: args.min_messages,
+            dir: args.output_dir.clone(),
+        }
+    } else {
+        if args.users.len() > 1 && args.output.is_some() {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--output can only be used with a single --user, use --output-dir instead",
+                )
+                .exit();
+        }
+        let users = args.users.iter().map(|&user| {
+            let path = args.output.clone().unwrap_or_else(|| {
+                args.output_dir
+                    .join(file_name(user, args.format, pseudonymizer.as_ref()))
+            });
+            (user, path)
+        });
+        Selection::Users(users.collect())
+    };
+    if args.output.is_none() {
+        fs::create_dir_all(&args.output_dir).map_err(|source| Error::Output {
+            path: args.output_dir.clone(),
+            source,
+        })?;
+    }
+    let mut outputs = Outputs::new(selection, args.format, pseudonymizer.clone())?;
 
-#[serde_as]
-#[derive(Debug, Deserialize)]
-struct DiscordMessageReference {
-    #[serde_as(as = "Option<DisplayFromStr>")]
-    message_id: Option<u64>,
+    let mut write_channel = |channel: &Channel, archive: Option<&ArchiveIndex>| {
+        for message in &channel.messages {
+            outputs.count_message(message.author);
+        }
+        let records = channel_records_by(channel, archive, &kind, &context, |author| {
+            outputs.wants(author)
+        });
+        for (user, mut record) in records {
+            if let Err(check) = quality.check(record.reply_text()) {
+                dropped.add(check);
+                continue;
+            }
+            if let Some(pseudonymizer) = &pseudonymizer {
+                pseudonymizer.record(&mut record);
+            }
+            let source = args.metadata.then(|| channel.info.clone());
+            outputs.write(user, Entry { record, source })?;
+        }
+        outputs.flush()
+    };
+    let redactor = Redactor::new(&args.redact.kinds());
+    let mut report = match &args.redact.redaction_report {
+        Some(path) => Some(RedactionReport::create(path)?),
+        None => None,
+    };
+    let mut redacted = Redactions::default();
+    let mut redact = |path: &Path, channel: &mut Channel| {
+        if redactor.is_empty() {
+            return Ok(());
+        }
+        let redactions = redactor.redact_channel(channel);
+        if redactions.total() > 0 {
+            println!("Redacted {redactions} from {path:?}");
+        }
+        redacted += redactions;
+        match &mut report {
+            Some(report) => report.write(path, &channel.info, &redactions),
+            None => Ok(()),
+        }
+    };
+    let rewrite_mentions = args.mentions != MentionStyle::Keep;
+    let mut names = NameTable::default();
+    if let Some(pseudonymizer) = &pseudonymizer {
+        names = names.with_pseudonymizer(pseudonymizer.clone());
+    }
+    let pseudonymize = |messages: &mut [Message]| {
+        if let Some(pseudonymizer) = &pseudonymizer {
+            pseudonymizer.rewrite_messages(messages);
+        }
+    };
+    // Opened once, as listing a packed archive means reading all of it.
+    let (files, mut failures, source) = source(&args.archive)?;
+    let failed = if args.cross_channel {
+        let mut channels = Vec::new();
+        let mut paths = Vec::new();
+        let failed = parse_each(&args.archive, &files, &source, |path, mut channel| {
+            if rewrite_mentions {
+                names.add_channel(&channel.info, &channel.messages);
+            }
+            filtered += filter.apply(&mut channel.messages);
+            paths.push(path.to_owned());
+            channels.push(channel);
+            Ok(())
+        })?;
+        for (path, channel) in paths.iter().zip(&mut channels) {
+            names.rewrite_messages(&mut channel.messages, args.mentions);
+            pseudonymize(&mut channel.messages);
+            normalizer.normalize_messages(&mut channel.messages);
+            redact(path, channel)?;
+            apply_media(&mut channel.messages, args.media);
+        }
+        let archive = ArchiveIndex::new(&channels);
+        for channel in &channels {
+            write_channel(channel, Some(&archive))?;
+        }
+        failed
+    } else {
+        if rewrite_mentions {
+            println!("Collecting names for mentions..");
+            parse_each(&args.archive, &files, &source, |_, channel| {
+                names.add_channel(&channel.info, &channel.messages);
+                Ok(())
+            })?;
+        }
+        parse_each(&args.archive, &files, &source, |path, mut channel| {
+            filtered += filter.apply(&mut channel.messages);
+            names.rewrite_messages(&mut channel.messages, args.mentions);
+            pseudonymize(&mut channel.messages);
+            normalizer.normalize_messages(&mut channel.messages);
+            redact(path, &mut channel)?;
+            apply_media(&mut channel.messages, args.media);
+            write_channel(&channel, None)
+        })?
+    };
+    failures.extend(failed);
+    for (user, path, count) in outputs.finish()? {
+        let user = label(user, pseudonymizer.as_ref());
+        println!("Wrote {count} records for {user} to {path:?}");
+    }
+    if filtered > 0 {
+        println!("Left out {filtered} bot, webhook or system messages");
+    }
+    if dropped.total() > 0 {
+        println!("Skipped {} replies: {dropped}", dropped.total());
+    }
+    if redacted.total() > 0 {
+        println!("Redacted {redacted} in total");
+    }
+    if let Some(report) = report {
+        report.finish()?;
+    }
+    report_failures(&failures);
+    println!("Done, see ya!");
+    Ok(ExitCode::SUCCESS)
 }
 
-fn main() {
-    let root_path = PathBuf::from(
-        std::env::args()
-            .nth(1)
-            .expect("Expected first argument to be path"),
-    );
-    let who_string = std::env::args()
-        .nth(2)
-        .expect("Expected a second argument of a discord user id");
-    let who: u64 = who_string.parse().unwrap();
+fn stats(args: ArchiveArgs) -> Result<ExitCode, Error> {
+    let mut files = 0;
+    let mut messages = 0;
+    let mut authors: HashMap<u64, usize> = HashMap::new();
+    let (channel_files, mut failures, source) = source(&args)?;
+    let failed = parse_each(&args, &channel_files, &source, |_, channel| {
+        files += 1;
+        messages += channel.messages.len();
+        for message in &channel.messages {
+            *authors.entry(message.author).or_default() += 1;
+        }
+        Ok(())
+    })?;
+    failures.extend(failed);
+    let mut authors: Vec<(u64, usize)> = authors.into_iter().collect();
+    authors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
 
-    let out_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(format!("./prompt-{who}.json"))
-        .unwrap();
+    println!("Files: {files}");
+    println!("Messages: {messages}");
+    println!("Authors: {}", authors.len());
+    println!("Top authors:");
+    for (author, count) in authors.iter().take(20) {
+        println!("  {author}: {count}");
+    }
+    report_failures(&failures);
+    Ok(ExitCode::SUCCESS)
+}
 
-    let mut channels: Vec<Vec<Message>> = Vec::with_capacity(256);
+fn validate(args: ArchiveArgs) -> Result<ExitCode, Error> {
+    let (files, skipped, source) = source(&args)?;
+    let total_files = files.len();
+    let load = |path: &Path| source.load(path);
+    let results = load_channels(&files, load, threads(&args), |progress| {
+        let Progress::Finished { path, result, .. } = progress else {
+            return ControlFlow::Continue(());
+        };
+        match result {
+            Ok(channel) => println!("{path:?}: ok, {} messages", channel.messages.len()),
+            Err(_) => println!("{path:?}: FAILED"),
+        }
+        stop_if_strict(&args, result)
+    });
+    let mut failures = skipped;
+    for result in results.into_iter().flatten() {
+        match result {
+            Ok(_) => {}
+            Err(e) if args.strict => return Err(e),
+            Err(e) => failures.push(e),
+        }
+    }
+    if failures.is_empty() {
+        println!("All {total_files} files parsed successfully");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        report_failures(&failures);
+        Ok(ExitCode::FAILURE)
+    }
+}
 
-    let channel_files = channel_files(root_path);
+/// Parse `channel_files` from `source`, handing each channel and the file it
+/// came from to `consume` in a stable order as soon as it is ready.
+///
+/// Returns the files that failed to load, or the first failure in `--strict`
+/// mode. An error from `consume` stops the run.
+fn parse_each(
+    args: &ArchiveArgs,
+    channel_files: &[PathBuf],
+    source: &Source,
+    mut consume: impl FnMut(&Path, Channel) -> Result<(), Error>,
+) -> Result<Vec<Error>, Error> {
     let parse_start = Instant::now();
     let total_files = channel_files.len();
-    let mut completed: usize = 0;
-
-    for messages_json in channel_files {
-        let our_number = completed;
-        completed += 1;
-        let start = Instant::now();
-        println!("Starting parsing on {messages_json:?} ({our_number}/{total_files})");
-        let file = OpenOptions::new().read(true).open(&messages_json).unwrap();
-        let data: Vec<DiscordMessage> = simd_json::from_reader(file).unwrap();
-        let mut messages: Vec<Message> = data
-            .into_iter()
-            .map(|v| Message {
-                id: v.id,
-                author: v.author.id,
-                content: v.content,
-                timestamp: v.timestamp,
-                reference: v.message_reference.map(|v| v.message_id).flatten(),
-            })
-            .collect();
-        messages.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        channels.push(messages);
-        let end = Instant::now();
-        let duration = end - start;
-        println!(
-            "Completed parsing on {messages_json:?} ({our_number}/{total_files}), took {}ms",
-            duration.as_millis()
-        );
+    let mut channels = 0;
+    let mut messages = 0;
+    let mut failures = Vec::new();
+    let mut fatal = None;
+
+    let on_progress = |progress: Progress| match progress {
+        Progress::Started { index, path } => {
+            println!("Starting parsing on {path:?} ({index}/{total_files})");
+            ControlFlow::Continue(())
+        }
+        Progress::Finished {
+            path,
+            completed,
+            elapsed,
+            result,
+            ..
+        } => {
+            match result {
+                Ok(_) => println!(
+                    "Completed parsing on {path:?} ({completed}/{total_files}), took {}ms",
+                    elapsed.as_millis()
+                ),
+                Err(e) => eprintln!("{e}, skipping.. ({completed}/{total_files})"),
+            }
+            stop_if_strict(args, result)
+        }
+    };
+    for_each_channel(
+        channel_files,
+        |path| source.load(path),
+        threads(args),
+        on_progress,
+        |index, result| match result {
+            Ok(channel) => {
+                channels += 1;
+                messages += channel.messages.len();
+                match consume(&channel_files[index], channel) {
+                    Ok(()) => ControlFlow::Continue(()),
+                    Err(e) => {
+                        fatal = Some(e);
+                        ControlFlow::Break(())
+                    }
+                }
+            }
+            Err(e) if args.strict => {
+                fatal = Some(e);
+                ControlFlow::Break(())
+            }
+            Err(e) => {
+                failures.push(e);
+                ControlFlow::Continue(())
+            }
+        },
+    );
+    if let Some(e) = fatal {
+        return Err(e);
     }
     println!(
-        "Completed all parsing in {} seconds, have {} messages from {} channels",
+        "Completed all parsing in {} seconds, have {messages} messages from {channels} channels",
         (Instant::now() - parse_start).as_secs(),
-        channels.iter().map(|v| v.len()).sum::<usize>(),
-        channels.len()
     );
-    let mut replies: Vec<Reply> = Vec::with_capacity(100_000);
-    for channel in channels {
-        for (index, message) in channel.iter().enumerate() {
-            if message.author != who || message.content.is_empty() {
-                continue;
-            }
-            let reply = message.content.clone();
-            let Some(prompt) = get_prompt(&channel, index, who) else {
-                continue;
-            };
-            replies.push(Reply { prompt, reply });
+    Ok(failures)
+}
+
+/// How the files of an archive are laid out.
+enum Source {
+    /// Channel directories or DiscordChatExporter files.
+    Archive,
+    /// A data package belonging to `owner`.
+    Package { owner: u64 },
+    /// A `.zip` or tarball.
+    Packed(Box<PackedArchive>),
+}
+
+impl Source {
+    fn load(&self, path: &Path) -> LoadResult {
+        match self {
+            Self::Archive => load_channel(path),
+            Self::Package { owner } => load_package_channel(path, *owner),
+            Self::Packed(archive) => archive.load(path),
         }
     }
-    serde_json::to_writer(out_file, &replies).unwrap();
-    println!("Done, see ya!");
 }
 
-fn get_prompt(messages: &[Message], index: usize, who: u64) -> Option<String> {
-    if index == 0 {
-        return None;
-    }
-    let mut innerdex = index - 1;
-    let reply = &messages[index];
-    let mut outputs: Vec<String> = Vec::new();
-    let mut reference_time = reply.timestamp;
-
-    if let Some(reference) = reply.reference {
-        for (reply_index, message) in messages[0..innerdex].iter().enumerate() {
-            if message.id == reference {
-                innerdex = reply_index;
-                reference_time = message.timestamp;
-                break;
-            }
+/// Find the files to parse and how to parse them, along with the parts of
+/// the archive that couldn't be searched. In `--strict` mode those fail the
+/// run instead.
+fn source(args: &ArchiveArgs) -> Result<(Vec<PathBuf>, Vec<Error>, Source), Error> {
+    if !args.package {
+        if let Some(kind) = PackedKind::detect(&args.archive) {
+            let archive = PackedArchive::open(&args.archive, kind)?;
+            return Ok((
+                archive.files().to_vec(),
+                Vec::new(),
+                Source::Packed(Box::new(archive)),
+            ));
         }
-    }
-    while innerdex != 0
-        && outputs.len() < 5
-        && messages[innerdex].author != who
-        && (messages[innerdex].timestamp - reference_time).num_minutes() <= 10
-    {
-        let prompt = &messages[innerdex];
-        innerdex -= 1;
-        if prompt.content.is_empty() {
-            continue;
+        let mut skipped = Vec::new();
+        let files = find_channel_files(&args.archive, &mut skipped)?;
+        if args.strict {
+            if let Some(e) = skipped.into_iter().next() {
+                return Err(e);
+            }
+            return Ok((files, Vec::new(), Source::Archive));
         }
-        outputs.push(prompt.content.clone());
-    }
-    if outputs.is_empty() {
-        None
-    } else {
-        outputs.reverse();
-        Some(outputs.join("\n"))
+        return Ok((files, skipped, Source::Archive));
     }
+    let owner = match args.owner {
+        Some(owner) => owner,
+        None => package_owner(&args.archive)?.ok_or_else(|| Error::UnknownOwner {
+            path: args.archive.clone(),
+        })?,
+    };
+    Ok((
+        package_files(&args.archive)?,
+        Vec::new(),
+        Source::Package { owner },
+    ))
 }
 
-fn walkdir(path: PathBuf) -> Vec<PathBuf> {
-    path.read_dir()
-        .unwrap()
-        .map(|v| v.unwrap().path())
-        .collect()
+fn threads(args: &ArchiveArgs) -> NonZeroUsize {
+    args.threads
+        .or_else(|| thread::available_parallelism().ok())
+        .unwrap_or(NonZeroUsize::MIN)
 }
 
-fn channel_files(root_path: PathBuf) -> Vec<PathBuf> {
-    let channel_dirs = walkdir(root_path);
-    let mut thread_dirs = Vec::with_capacity(1024);
-    let mut files = Vec::with_capacity(1024);
-
-    for dir in &channel_dirs {
-        let threads_dir = dir.join("threads");
-        if threads_dir.exists() {
-            let mut dirs = walkdir(threads_dir);
-            thread_dirs.append(&mut dirs);
-        } else {
-            eprintln!("Found no threads in {dir:?}, skipping..");
-        }
+fn stop_if_strict<T>(args: &ArchiveArgs, result: &Result<T, Error>) -> ControlFlow<()> {
+    if args.strict && result.is_err() {
+        ControlFlow::Break(())
+    } else {
+        ControlFlow::Continue(())
     }
+}
 
-    for dir in &channel_dirs {
-        let messages_path = dir.join("channel_messages.json");
-        if messages_path.exists() {
-            files.push(messages_path);
-        } else {
-            eprintln!("Found no channel_messages.json in {dir:?}, skipping..");
-        }
+fn report_failures(failures: &[Error]) {
+    if failures.is_empty() {
+        return;
     }
-
-    for dir in thread_dirs {
-        let messages_path = dir.join("thread_messages.json");
-        if messages_path.exists() {
-            files.push(messages_path);
-        } else {
-            eprintln!("Found no thread_messages.json in {dir:?}, skipping..");
-        }
+    eprintln!("{} files failed and were skipped:", failures.len());
+    for failure in failures {
+        eprintln!("  {failure}");
     }
-    files
 }
@@ -0,0 +1,117 @@
+//! Deciding what happens to attachments, embeds and stickers.
+//!
+//! Messages keep their [`Media`] when loaded. [`apply_media`] then either
+//! drops it, which matches what older versions of this crate did, spells it
+//! out in the message text, or leaves it to be written as structured fields
+//! alongside each record.
+
+use std::{fmt, str::FromStr};
+
+use crate::model::{Attachment, Embed, Media, Message};
+
+/// What to do with the [`Media`] of each message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MediaMode {
+    /// Leave it out entirely. Messages that only carry media are then empty
+    /// and never become records.
+    #[default]
+    Drop,
+    /// Append placeholders such as `[image: cat.png]` to the message text.
+    Placeholders,
+    /// Keep it, so records carry `attachments`, `embeds` and `stickers`
+    /// fields for the reply (or each turn).
+    Fields,
+}
+
+impl FromStr for MediaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Self::Drop),
+            "placeholders" => Ok(Self::Placeholders),
+            "fields" => Ok(Self::Fields),
+            _ => Err(format!(
+                "unknown media mode {s:?}, expected one of: drop, placeholders, fields"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MediaMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Drop => "drop",
+            Self::Placeholders => "placeholders",
+            Self::Fields => "fields",
+        })
+    }
+}
+
+/// Apply `mode` to every message in a channel.
+pub fn apply_media(messages: &mut [Message], mode: MediaMode) {
+    for message in messages {
+        match mode {
+            MediaMode::Drop => message.media = Media::default(),
+            MediaMode::Placeholders => {
+                let media = std::mem::take(&mut message.media);
+                for placeholder in placeholders(&media) {
+                    if !message.content.is_empty() {
+                        message.content.push('\n');
+                    }
+                    message.content.push_str(&placeholder);
+                }
+            }
+            MediaMode::Fields => {}
+        }
+    }
+}
+
+/// A line of text standing in for each piece of `media`.
+///
+/// Embeds without a title or description are skipped, as those are link
+/// previews whose link is already in the message.
+pub fn placeholders(media: &Media) -> Vec<String> {
+    let attachments = media
+        .attachments
+        .iter()
+        .map(|v| format!("[{}: {}]", attachment_kind(v), v.filename));
+    let embeds = media.embeds.iter().filter_map(embed_placeholder);
+    let stickers = media
+        .stickers
+        .iter()
+        .map(|v| format!("[sticker: {}]", v.name));
+    attachments.chain(embeds).chain(stickers).collect()
+}
+
+fn embed_placeholder(embed: &Embed) -> Option<String> {
+    match (&embed.title, &embed.description) {
+        (Some(title), _) => Some(format!("[embed: {title}]")),
+        (None, Some(description)) => Some(format!("[embed: {description}]")),
+        (None, None) => None,
+    }
+}
+
+/// `image`, `video`, `audio` or `file`, going by the content type if known
+/// and the file extension otherwise.
+pub fn attachment_kind(attachment: &Attachment) -> &'static str {
+    if let Some(content_type) = &attachment.content_type {
+        for kind in ["image", "video", "audio"] {
+            if content_type.starts_with(kind) {
+                return kind;
+            }
+        }
+        return "file";
+    }
+    let extension = attachment
+        .filename
+        .rsplit_once('.')
+        .map(|(_, v)| v.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" | "jpg" | "jpeg" | "gif" | "webp" | "bmp" | "avif" => "image",
+        "mp4" | "webm" | "mov" | "mkv" => "video",
+        "mp3" | "ogg" | "wav" | "flac" | "m4a" | "opus" => "audio",
+        _ => "file",
+    }
+}
@@ -0,0 +1,360 @@
+//! Rewriting `<@user>`, `<@&role>` and `<#channel>` mention tokens into
+//! something readable.
+//!
+//! Messages only give names for their own author and the users they mention,
+//! so names are gathered across a whole archive into a [`NameTable`] before
+//! any mention is rewritten.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::{meta::ChannelInfo, model::Message, pseudonym::Pseudonymizer};
+
+/// What to turn mention tokens into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MentionStyle {
+    /// Leave them as they are.
+    #[default]
+    Keep,
+    /// `@username`, `@role` and `#channel`.
+    Names,
+    /// Like [`MentionStyle::Names`], but users become a pseudonym such as
+    /// `@user_0042`, numbered in the order users appear in the archive unless
+    /// the table has a [`Pseudonymizer`].
+    Pseudonyms,
+}
+
+impl FromStr for MentionStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "names" => Ok(Self::Names),
+            "pseudonyms" => Ok(Self::Pseudonyms),
+            _ => Err(format!(
+                "unknown mention style {s:?}, expected one of: keep, names, pseudonyms"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MentionStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Keep => "keep",
+            Self::Names => "names",
+            Self::Pseudonyms => "pseudonyms",
+        })
+    }
+}
+
+/// A mention token found in message content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mention {
+    User(u64),
+    Role(u64),
+    Channel(u64),
+}
+
+impl Mention {
+    /// Parse the token at the start of `text`, returning it and its length.
+    pub(crate) fn parse(text: &str) -> Option<(Self, usize)> {
+        let end = text.find('>')?;
+        let inner = text.get(1..end)?;
+        let (kind, id): (fn(u64) -> Self, &str) = if let Some(id) = inner.strip_prefix("@&") {
+            (Self::Role, id)
+        } else if let Some(id) = inner.strip_prefix("@!").or(inner.strip_prefix('@')) {
+            (Self::User, id)
+        } else if let Some(id) = inner.strip_prefix('#') {
+            (Self::Channel, id)
+        } else {
+            return None;
+        };
+        if id.is_empty() || !id.bytes().all(|v| v.is_ascii_digit()) {
+            return None;
+        }
+        Some((kind(id.parse().ok()?), end + 1))
+    }
+}
+
+/// Every mention token in `content`, in order.
+fn mentions(content: &str) -> impl Iterator<Item = Mention> + '_ {
+    content
+        .match_indices('<')
+        .filter_map(|(start, _)| Mention::parse(&content[start..]))
+        .map(|(mention, _)| mention)
+}
+
+/// Rewrite the mention tokens in `content` with `replace`, leaving any it
+/// returns `None` for as they are.
+pub(crate) fn replace_mentions(
+    content: &str,
+    mut replace: impl FnMut(Mention) -> Option<String>,
+) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find('<') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match Mention::parse(rest).and_then(|(mention, len)| Some((replace(mention)?, len))) {
+            Some((text, len)) => {
+                output.push_str(&text);
+                rest = &rest[len..];
+            }
+            None => {
+                output.push('<');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+#[derive(Debug, Clone)]
+struct User {
+    name: Option<String>,
+    /// Order of first appearance, used for pseudonyms.
+    number: usize,
+}
+
+/// Names for the users, roles and channels of an archive.
+#[derive(Debug, Clone, Default)]
+pub struct NameTable {
+    users: HashMap<u64, User>,
+    roles: HashMap<u64, String>,
+    channels: HashMap<u64, String>,
+    pseudonymizer: Option<Pseudonymizer>,
+}
+
+impl NameTable {
+    /// Label users with `pseudonymizer` in place of their names, whatever the
+    /// [`MentionStyle`].
+    pub fn with_pseudonymizer(self, pseudonymizer: Pseudonymizer) -> Self {
+        Self {
+            pseudonymizer: Some(pseudonymizer),
+            ..self
+        }
+    }
+
+    /// Learn the names a channel gives. The first name seen for an id is
+    /// kept.
+    ///
+    /// Users are numbered as they are first seen, so adding channels in the
+    /// same order always gives the same pseudonyms.
+    pub fn add_channel(&mut self, info: &ChannelInfo, messages: &[Message]) {
+        for (id, name) in [
+            (info.channel_id, &info.channel_name),
+            (info.thread_id, &info.thread_name),
+        ] {
+            if let (Some(id), Some(name)) = (id, name) {
+                self.channels.entry(id).or_insert_with(|| name.clone());
+            }
+        }
+        for message in messages {
+            self.add_user(message.author, None);
+            for (id, name) in &message.names.users {
+                self.add_user(*id, Some(name));
+            }
+            for (id, name) in &message.names.roles {
+                self.roles.entry(*id).or_insert_with(|| name.clone());
+            }
+            for mention in mentions(&message.content) {
+                if let Mention::User(id) = mention {
+                    self.add_user(id, None);
+                }
+            }
+        }
+    }
+
+    fn add_user(&mut self, id: u64, name: Option<&String>) {
+        let number = self.users.len() + 1;
+        let user = self.users.entry(id).or_insert(User { name: None, number });
+        if user.name.is_none() {
+            user.name = name.cloned();
+        }
+    }
+
+    /// Rewrite the mention tokens in every message's content.
+    pub fn rewrite_messages(&self, messages: &mut [Message], style: MentionStyle) {
+        if style == MentionStyle::Keep {
+            return;
+        }
+        for message in messages {
+            if message.content.contains('<') {
+                message.content = self.rewrite(&message.content, style);
+            }
+        }
+    }
+
+    /// Rewrite the mention tokens in `content`. Ids with no known name become
+    /// `@unknown-user`, `@unknown-role` or `#unknown-channel`.
+    pub fn rewrite(&self, content: &str, style: MentionStyle) -> String {
+        if style == MentionStyle::Keep {
+            return content.to_owned();
+        }
+        replace_mentions(content, |mention| Some(self.mention_text(mention, style)))
+    }
+
+    fn mention_text(&self, mention: Mention, style: MentionStyle) -> String {
+        match mention {
+            Mention::User(id) => {
+                if let Some(pseudonymizer) = &self.pseudonymizer {
+                    return format!("@{}", pseudonymizer.label(id));
+                }
+                let user = self.users.get(&id);
+                match user {
+                    Some(user) if style == MentionStyle::Pseudonyms => {
+                        format!("@user_{:04}", user.number)
+                    }
+                    _ => match user.and_then(|v| v.name.as_deref()) {
+                        Some(name) => format!("@{name}"),
+                        None => "@unknown-user".to_owned(),
+                    },
+                }
+            }
+            Mention::Role(id) => match self.roles.get(&id) {
+                Some(name) => format!("@{name}"),
+                None => "@unknown-role".to_owned(),
+            },
+            Mention::Channel(id) => match self.channels.get(&id) {
+                Some(name) => format!("#{name}"),
+                None => "#unknown-channel".to_owned(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Names;
+
+    fn message(author: u64, content: &str, users: &[(u64, &str)]) -> Message {
+        Message {
+            author,
+            content: content.to_owned(),
+            names: Names {
+                users: users
+                    .iter()
+                    .map(|&(id, name)| (id, name.to_owned()))
+                    .collect(),
+                roles: vec![(7, "mods".to_owned())],
+            },
+            ..Default::default()
+        }
+    }
+
+    fn table() -> NameTable {
+        let info = ChannelInfo {
+            channel_id: Some(100),
+            channel_name: Some("general".to_owned()),
+            ..Default::default()
+        };
+        let mut names = NameTable::default();
+        names.add_channel(
+            &info,
+            &[
+                message(1, "hi <@2>", &[(1, "alice"), (2, "bob")]),
+                message(3, "hello", &[(3, "carol")]),
+            ],
+        );
+        names
+    }
+
+    #[test]
+    fn parse_tokens() {
+        assert_eq!(Mention::parse("<@12>"), Some((Mention::User(12), 5)));
+        assert_eq!(Mention::parse("<@!12> rest"), Some((Mention::User(12), 6)));
+        assert_eq!(Mention::parse("<@&7>"), Some((Mention::Role(7), 5)));
+        assert_eq!(Mention::parse("<#100>"), Some((Mention::Channel(100), 6)));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_tokens() {
+        for text in [
+            "<@abc>",
+            "<@12a>",
+            "<@>",
+            "<@&>",
+            "<#-1>",
+            "<@ 12>",
+            "<@12",
+            "<:smile:123>",
+            "<@99999999999999999999999>",
+        ] {
+            assert_eq!(Mention::parse(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn rewrite_names() {
+        let names = table();
+        assert_eq!(
+            names.rewrite(
+                "<@1> <@!2> <@&7> in <#100>, not <@9> <@&8> <#9>",
+                MentionStyle::Names
+            ),
+            "@alice @bob @mods in #general, not @unknown-user @unknown-role #unknown-channel"
+        );
+        assert_eq!(
+            names.rewrite("<@1> <@!2>", MentionStyle::Keep),
+            "<@1> <@!2>"
+        );
+    }
+
+    #[test]
+    fn rewrite_leaves_malformed_tokens() {
+        let names = table();
+        for (text, expected) in [
+            ("<@bob>", "<@bob>"),
+            ("a < b", "a < b"),
+            ("2 <3 <@1", "2 <3 <@1"),
+            ("<@1 > <#x>", "<@1 > <#x>"),
+            ("<<@1>", "<@alice"),
+        ] {
+            assert_eq!(names.rewrite(text, MentionStyle::Names), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn rewrite_multibyte_text() {
+        let names = table();
+        assert_eq!(
+            names.rewrite("héllo <@1>、привет<#100>🙂<@&7>", MentionStyle::Names),
+            "héllo @alice、привет#general🙂@mods"
+        );
+        assert_eq!(
+            names.rewrite("日本<語 <@2>", MentionStyle::Names),
+            "日本<語 @bob"
+        );
+    }
+
+    #[test]
+    fn pseudonyms_number_users_in_order_seen() {
+        let names = table();
+        assert_eq!(
+            names.rewrite("<@1> <@2> <@3> <@9>", MentionStyle::Pseudonyms),
+            "@user_0001 @user_0002 @user_0003 @unknown-user"
+        );
+        // The same archive always numbers users the same way.
+        for _ in 0..3 {
+            assert_eq!(
+                table().rewrite("<@3> <@2> <@1>", MentionStyle::Pseudonyms),
+                "@user_0003 @user_0002 @user_0001"
+            );
+        }
+    }
+
+    #[test]
+    fn pseudonymizer_labels_users_in_every_style() {
+        let pseudonymizer = Pseudonymizer::new(b"key");
+        let names = table().with_pseudonymizer(pseudonymizer.clone());
+        let expected = format!("@{} @mods", pseudonymizer.label(1));
+        assert_eq!(names.rewrite("<@1> <@&7>", MentionStyle::Names), expected);
+        assert_eq!(
+            names.rewrite("<@1> <@&7>", MentionStyle::Pseudonyms),
+            expected
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Working out which guild, channel and thread a messages file belongs to.
+//!
+//! Archives may keep a `channel.json` next to `channel_messages.json` and a
+//! `thread.json` next to each `thread_messages.json`, and data packages keep a
+//! `channel.json` in each channel directory. Where these are missing, ids are
+//! taken from directory names instead.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+
+/// Where a channel's messages came from. Every field is optional, as
+/// archives vary in how much they record.
+///
+/// For threads, `channel_id` and `channel_name` describe the parent channel.
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct ChannelInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_name: Option<String>,
+}
+
+impl ChannelInfo {
+    /// Fill in whatever is missing from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            guild_id: self.guild_id.or(other.guild_id),
+            channel_id: self.channel_id.or(other.channel_id),
+            channel_name: self.channel_name.or(other.channel_name),
+            thread_id: self.thread_id.or(other.thread_id),
+            thread_name: self.thread_name.or(other.thread_name),
+        }
+    }
+
+    /// Whether the messages are from a thread rather than a channel.
+    pub fn is_thread(&self) -> bool {
+        self.thread_id.is_some() || self.thread_name.is_some()
+    }
+}
+
+/// A `channel.json` or `thread.json`.
+#[serde_as]
+#[derive(Debug, Default, Deserialize)]
+struct MetaFile {
+    #[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+    #[serde(default)]
+    guild_id: Option<u64>,
+    #[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+    #[serde(default)]
+    parent_id: Option<u64>,
+    /// Data packages nest the guild rather than giving `guild_id`.
+    #[serde(default)]
+    guild: Option<MetaGuild>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct MetaGuild {
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    id: u64,
+}
+
+impl MetaFile {
+    /// Read `file_name` from `dir`, treating a missing or malformed file as
+    /// empty since metadata is only ever a nice-to-have.
+    fn read(dir: &Path, file_name: &str, read: &impl Fn(&Path) -> Option<Vec<u8>>) -> Self {
+        read(&dir.join(file_name))
+            .and_then(|mut data| simd_json::from_slice(&mut data).ok())
+            .unwrap_or_default()
+    }
+
+    fn guild_id(&self) -> Option<u64> {
+        self.guild_id.or(self.guild.as_ref().map(|v| v.id))
+    }
+}
+
+/// The id a directory name stands for, if it is one: either bare, or prefixed
+/// with `c` as in data packages.
+fn dir_id(dir: &Path) -> Option<u64> {
+    let name = dir.file_name()?.to_str()?;
+    name.strip_prefix('c').unwrap_or(name).parse().ok()
+}
+
+/// A directory name that is not just an id, to use as a channel name.
+fn dir_name(dir: &Path) -> Option<String> {
+    let name = dir.file_name()?.to_str()?;
+    dir_id(dir).is_none().then(|| name.to_owned())
+}
+
+/// Describe the messages file at `path` from the metadata files and
+/// directory names around it, using `read` to fetch metadata files.
+pub fn layout_info(path: &Path, read: impl Fn(&Path) -> Option<Vec<u8>>) -> ChannelInfo {
+    let Some(dir) = path.parent() else {
+        return ChannelInfo::default();
+    };
+    let threads_dir = dir
+        .parent()
+        .filter(|v| v.file_name().is_some_and(|v| v == "threads"));
+    match threads_dir.and_then(Path::parent) {
+        Some(channel_dir) => {
+            let thread = MetaFile::read(dir, "thread.json", &read);
+            let channel = MetaFile::read(channel_dir, "channel.json", &read);
+            ChannelInfo {
+                guild_id: thread.guild_id().or(channel.guild_id()),
+                channel_id: thread.parent_id.or(channel.id).or(dir_id(channel_dir)),
+                channel_name: channel.name.or(dir_name(channel_dir)),
+                thread_id: thread.id.or(dir_id(dir)),
+                thread_name: thread.name.or(dir_name(dir)),
+            }
+        }
+        None => {
+            let channel = MetaFile::read(dir, "channel.json", &read);
+            ChannelInfo {
+                guild_id: channel.guild_id(),
+                channel_id: channel.id.or(dir_id(dir)),
+                channel_name: channel.name.or(dir_name(dir)),
+                thread_id: None,
+                thread_name: None,
+            }
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Types describing both the raw Discord archive format and the simplified
+//! records this crate produces from it.
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::meta::ChannelInfo;
+
+/// A prompt/reply pair, where `reply` was written by the target user and
+/// `prompt` is the conversation that led up to it.
+#[derive(Debug, Serialize, Clone)]
+pub struct Reply {
+    pub prompt: String,
+    pub reply: String,
+    /// Anything attached to the reply.
+    #[serde(flatten)]
+    pub media: Media,
+}
+
+/// One message in a [`Conversation`].
+#[derive(Debug, Serialize, Clone)]
+pub struct Turn {
+    pub role: Role,
+    pub author: AuthorId,
+    pub content: String,
+    #[serde(flatten)]
+    pub media: Media,
+}
+
+impl Turn {
+    /// A turn for `message`, where `who` is the user the dataset is for.
+    pub fn new(message: &Message, who: u64) -> Self {
+        Self {
+            role: if message.author == who {
+                Role::Assistant
+            } else {
+                Role::User
+            },
+            author: AuthorId::Id(message.author),
+            content: message.content.clone(),
+            media: message.media.clone(),
+        }
+    }
+}
+
+/// Who wrote a [`Turn`]: their user id, or a label standing in for it, see
+/// [`Pseudonymizer`](crate::pseudonym::Pseudonymizer).
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AuthorId {
+    Id(u64),
+    Pseudonym(String),
+}
+
+/// Who is speaking in a [`Turn`], from the point of view of a chat model
+/// trained to imitate the target user.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Anyone other than the target user.
+    User,
+    /// The target user.
+    Assistant,
+    /// Instructions for the model, only used in [`ChatExample`]s.
+    System,
+}
+
+/// An exchange ending in a message by the target user, oldest turn first.
+#[derive(Debug, Serialize, Clone)]
+pub struct Conversation {
+    pub turns: Vec<Turn>,
+}
+
+/// A message in a [`ChatExample`].
+#[derive(Debug, Serialize, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+/// A prompt/reply pair in the OpenAI/ChatML fine-tuning layout.
+#[derive(Debug, Serialize, Clone)]
+pub struct ChatExample {
+    pub messages: Vec<ChatMessage>,
+}
+
+impl ChatExample {
+    /// An example where the user says `prompt` and the assistant answers
+    /// `reply`, optionally preceded by a system message.
+    pub fn new(system_prompt: Option<&str>, prompt: String, reply: String) -> Self {
+        let system = system_prompt.map(|content| ChatMessage {
+            role: Role::System,
+            content: content.to_owned(),
+        });
+        let messages = system
+            .into_iter()
+            .chain([
+                ChatMessage {
+                    role: Role::User,
+                    content: prompt,
+                },
+                ChatMessage {
+                    role: Role::Assistant,
+                    content: reply,
+                },
+            ])
+            .collect();
+        Self { messages }
+    }
+}
+
+/// A single entry in an output dataset.
+#[derive(Debug, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Record {
+    Pair(Reply),
+    Conversation(Conversation),
+    Chat(ChatExample),
+    Monologue(Monologue),
+}
+
+impl Record {
+    /// The target user's message that ends the record.
+    pub fn reply_text(&self) -> &str {
+        match self {
+            Self::Pair(v) => &v.reply,
+            Self::Conversation(v) => v.turns.last().map_or("", |v| &v.content),
+            Self::Chat(v) => v.messages.last().map_or("", |v| &v.content),
+            Self::Monologue(v) => &v.reply,
+        }
+    }
+}
+
+/// A message by the target user on its own, for sources such as data
+/// packages that hold no one else's messages to use as a prompt.
+#[derive(Debug, Serialize, Clone)]
+pub struct Monologue {
+    pub reply: String,
+    #[serde(flatten)]
+    pub media: Media,
+}
+
+/// A [`Record`] alongside the channel it was built from, when that is
+/// wanted in the output.
+#[derive(Debug, Serialize, Clone)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub record: Record,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<ChannelInfo>,
+}
+
+/// The messages from one file, oldest first, and where they came from.
+#[derive(Debug, Clone, Default)]
+pub struct Channel {
+    pub info: ChannelInfo,
+    pub messages: Vec<Message>,
+}
+
+/// A single message, stripped down to what pairing needs.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Message {
+    pub id: u64,
+    pub content: String,
+    pub timestamp: chrono::DateTime<Utc>,
+    pub author: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<Reference>,
+    #[serde(flatten)]
+    pub media: Media,
+    /// Sent by a bot account.
+    #[serde(skip_serializing_if = "is_false")]
+    pub bot: bool,
+    /// Sent through a webhook rather than by a user.
+    #[serde(skip_serializing_if = "is_false")]
+    pub webhook: bool,
+    /// A notice generated by Discord, such as a member joining or a message
+    /// being pinned, rather than an ordinary message or reply.
+    #[serde(skip_serializing_if = "is_false")]
+    pub system: bool,
+    /// Names for the author and anyone mentioned, for [`NameTable`].
+    ///
+    /// [`NameTable`]: crate::mentions::NameTable
+    #[serde(skip)]
+    pub names: Names,
+}
+
+/// The names a message gives for the users and roles it involves, by id.
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    pub users: Vec<(u64, String)>,
+    pub roles: Vec<(u64, String)>,
+}
+
+fn is_false(v: &bool) -> bool {
+    !v
+}
+
+impl Message {
+    /// Whether the message has neither text nor anything attached.
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty() && self.media.is_empty()
+    }
+}
+
+/// Files, link previews and stickers sent with a message. How these end up in
+/// a dataset is chosen with [`MediaMode`](crate::media::MediaMode).
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Media {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<Embed>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stickers: Vec<Sticker>,
+}
+
+impl Media {
+    pub fn is_empty(&self) -> bool {
+        self.attachments.is_empty() && self.embeds.is_empty() && self.stickers.is_empty()
+    }
+}
+
+/// An uploaded file.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    #[serde(default)]
+    pub filename: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The MIME type, such as `image/png`, when the archive records it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Size in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// A link preview or bot-provided rich content.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Embed {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sticker {
+    pub name: String,
+}
+
+/// The message another message replies to or forwards, which may be in a
+/// different channel or guild.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub message_id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<u64>,
+}
+
+/// A message as it appears in `channel_messages.json`/`thread_messages.json`.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct DiscordMessage {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    pub content: String,
+    pub timestamp: chrono::DateTime<Utc>,
+    pub author: DiscordAuthor,
+    pub message_reference: Option<DiscordMessageReference>,
+    /// 0 for ordinary messages and 19 for replies, anything else is a
+    /// system message.
+    #[serde(rename = "type", default)]
+    pub kind: u32,
+    #[serde(default)]
+    pub webhook_id: Option<String>,
+    /// Users mentioned in `content`.
+    #[serde(default)]
+    pub mentions: Vec<DiscordAuthor>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub embeds: Vec<Embed>,
+    /// Older archives call these `stickers`.
+    #[serde(default, alias = "stickers")]
+    pub sticker_items: Vec<Sticker>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct DiscordAuthor {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    #[serde(default)]
+    pub bot: bool,
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+impl DiscordAuthor {
+    /// The id and username, if the archive recorded one.
+    fn name(self) -> Option<(u64, String)> {
+        Some((self.id, self.username?))
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct DiscordMessageReference {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub message_id: Option<u64>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub guild_id: Option<u64>,
+}
+
+impl DiscordMessageReference {
+    /// The reference, if it names a message.
+    pub fn into_reference(self) -> Option<Reference> {
+        Some(Reference {
+            message_id: self.message_id?,
+            channel_id: self.channel_id,
+            guild_id: self.guild_id,
+        })
+    }
+}
+
+impl From<DiscordMessage> for Message {
+    fn from(v: DiscordMessage) -> Self {
+        Self {
+            id: v.id,
+            author: v.author.id,
+            content: v.content,
+            timestamp: v.timestamp,
+            reference: v
+                .message_reference
+                .and_then(DiscordMessageReference::into_reference),
+            media: Media {
+                attachments: v.attachments,
+                embeds: v.embeds,
+                stickers: v.sticker_items,
+            },
+            bot: v.author.bot,
+            webhook: v.webhook_id.is_some(),
+            system: !matches!(v.kind, 0 | 19),
+            names: Names {
+                users: [v.author]
+                    .into_iter()
+                    .chain(v.mentions)
+                    .filter_map(DiscordAuthor::name)
+                    .collect(),
+                roles: Vec::new(),
+            },
+        }
+    }
+}
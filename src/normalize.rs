@@ -0,0 +1,296 @@
+//! Tidying Discord-specific syntax in message text: custom emoji, `<t:...>`
+//! timestamps, spoilers and markdown.
+//!
+//! Code blocks and inline code are always left exactly as they are.
+
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use chrono::DateTime;
+use regex::{Captures, Regex, Replacer};
+
+use crate::model::Message;
+
+/// What to do with `||spoiler||` markers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpoilerMode {
+    /// Leave them as they are.
+    #[default]
+    Keep,
+    /// Remove the markers but keep the hidden text.
+    Unwrap,
+    /// Replace the whole spoiler with `[spoiler]`.
+    Hide,
+}
+
+impl FromStr for SpoilerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "unwrap" => Ok(Self::Unwrap),
+            "hide" => Ok(Self::Hide),
+            _ => Err(format!(
+                "unknown spoiler mode {s:?}, expected one of: keep, unwrap, hide"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SpoilerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Keep => "keep",
+            Self::Unwrap => "unwrap",
+            Self::Hide => "hide",
+        })
+    }
+}
+
+/// Which normalizations to apply. Everything is off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NormalizeOptions {
+    /// Turn `<:pepe:1234>` and `<a:dance:5678>` into `:pepe:` and `:dance:`.
+    pub emoji: bool,
+    /// Turn `<t:1700000000:R>` into `2023-11-14T22:13:20Z`, or just the date
+    /// for the date-only styles.
+    pub timestamps: bool,
+    pub spoilers: SpoilerMode,
+    /// Remove bold, italic, underline and strikethrough markers, heading,
+    /// subtext and quote prefixes, and reduce masked links to their text.
+    pub strip_markdown: bool,
+}
+
+/// Applies [`NormalizeOptions`] to message text.
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    options: NormalizeOptions,
+    code: Regex,
+    emoji: Regex,
+    timestamp: Regex,
+    spoiler: Regex,
+    /// Each markdown pattern and what to replace it with.
+    markdown: Vec<(Regex, &'static str)>,
+}
+
+impl Normalizer {
+    pub fn new(options: NormalizeOptions) -> Self {
+        let regex = |pattern: &str| Regex::new(pattern).expect("valid pattern");
+        Self {
+            options,
+            code: regex(r"(?s)```.*?```|``[^`].*?``|`[^`]+`"),
+            emoji: regex(r"<a?:(\w+):\d+>"),
+            timestamp: regex(r"<t:(-?\d+)(?::([tTdDfFR]))?>"),
+            spoiler: regex(r"(?s)\|\|(.+?)\|\|"),
+            markdown: [
+                (r"\*\*\*(\S(?:.*?\S)?)\*\*\*", "$1"),
+                (r"\*\*(\S(?:.*?\S)?)\*\*", "$1"),
+                (r"\*([^\s*](?:[^*]*?[^\s*])?)\*", "$1"),
+                (r"__(\S(?:.*?\S)?)__", "$1"),
+                // Underscores only mark italics outside of words, unlike in
+                // `snake_case`.
+                (r"(^|\W)_([^\s_](?:[^_]*?[^\s_])?)_(\W|$)", "$1$2$3"),
+                (r"~~(\S(?:.*?\S)?)~~", "$1"),
+                (r"\[([^\]]+)\]\(<?https?://[^)\s]+?>?\)", "$1"),
+                (r"(?m)^(?:#{1,3}|-#|>>>|>) ", ""),
+            ]
+            .map(|(pattern, replacement)| (regex(pattern), replacement))
+            .into(),
+        }
+    }
+
+    /// Whether this normalizer leaves all text unchanged.
+    pub fn is_noop(&self) -> bool {
+        self.options == NormalizeOptions::default()
+    }
+
+    /// Normalize the content of every message in a channel.
+    pub fn normalize_messages(&self, messages: &mut [Message]) {
+        if self.is_noop() {
+            return;
+        }
+        for message in messages {
+            if let Cow::Owned(content) = self.normalize(&message.content) {
+                message.content = content;
+            }
+        }
+    }
+
+    /// Normalize `text`, leaving code untouched.
+    pub fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.is_noop() {
+            return Cow::Borrowed(text);
+        }
+        let mut output = String::with_capacity(text.len());
+        let mut last = 0;
+        for code in self.code.find_iter(text) {
+            output.push_str(&self.normalize_prose(&text[last..code.start()]));
+            output.push_str(code.as_str());
+            last = code.end();
+        }
+        output.push_str(&self.normalize_prose(&text[last..]));
+        if output == text {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned(output)
+        }
+    }
+
+    /// Normalize text known not to contain code.
+    fn normalize_prose<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if self.options.emoji {
+            text = replace(text, &self.emoji, ":${1}:");
+        }
+        if self.options.timestamps {
+            text = replace(text, &self.timestamp, |v: &Captures| {
+                let Some(time) = v[1]
+                    .parse()
+                    .ok()
+                    .and_then(|v| DateTime::from_timestamp(v, 0))
+                else {
+                    return v[0].to_owned();
+                };
+                match v.get(2).map(|v| v.as_str()) {
+                    Some("d" | "D") => time.format("%Y-%m-%d").to_string(),
+                    _ => time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                }
+            });
+        }
+        match self.options.spoilers {
+            SpoilerMode::Keep => {}
+            SpoilerMode::Unwrap => text = replace(text, &self.spoiler, "${1}"),
+            SpoilerMode::Hide => text = replace(text, &self.spoiler, "[spoiler]"),
+        }
+        if self.options.strip_markdown {
+            for (pattern, replacement) in &self.markdown {
+                text = replace(text, pattern, *replacement);
+            }
+        }
+        text
+    }
+}
+
+/// Like [`Regex::replace_all`], but keeps borrowing `text` if nothing matched.
+fn replace<'a>(text: Cow<'a, str>, pattern: &Regex, replacer: impl Replacer) -> Cow<'a, str> {
+    match pattern.replace_all(&text, replacer) {
+        Cow::Borrowed(_) => text,
+        Cow::Owned(replaced) => Cow::Owned(replaced),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(options: NormalizeOptions, text: &str) -> String {
+        Normalizer::new(options).normalize(text).into_owned()
+    }
+
+    fn all() -> NormalizeOptions {
+        NormalizeOptions {
+            emoji: true,
+            timestamps: true,
+            spoilers: SpoilerMode::Unwrap,
+            strip_markdown: true,
+        }
+    }
+
+    #[test]
+    fn noop_borrows() {
+        let normalizer = Normalizer::new(NormalizeOptions::default());
+        assert!(normalizer.is_noop());
+        assert!(matches!(
+            normalizer.normalize("**hi** <:pepe:1>"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn emoji() {
+        let options = NormalizeOptions {
+            emoji: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(options, "gg <:pepe:1234> <a:dance:5678>"),
+            "gg :pepe: :dance:"
+        );
+        assert_eq!(normalize(options, "<@1234> <#5678>"), "<@1234> <#5678>");
+    }
+
+    #[test]
+    fn timestamps() {
+        let options = NormalizeOptions {
+            timestamps: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(options, "at <t:1700000000:R>"),
+            "at 2023-11-14T22:13:20Z"
+        );
+        assert_eq!(normalize(options, "<t:1700000000>"), "2023-11-14T22:13:20Z");
+        assert_eq!(normalize(options, "on <t:1700000000:D>"), "on 2023-11-14");
+        assert_eq!(
+            normalize(options, "<t:99999999999999999999:R>"),
+            "<t:99999999999999999999:R>"
+        );
+    }
+
+    #[test]
+    fn spoilers() {
+        let text = "it was ||the butler|| all along";
+        let with = |spoilers| NormalizeOptions {
+            spoilers,
+            ..Default::default()
+        };
+        assert_eq!(normalize(with(SpoilerMode::Keep), text), text);
+        assert_eq!(
+            normalize(with(SpoilerMode::Unwrap), text),
+            "it was the butler all along"
+        );
+        assert_eq!(
+            normalize(with(SpoilerMode::Hide), text),
+            "it was [spoiler] all along"
+        );
+    }
+
+    #[test]
+    fn markdown() {
+        let options = NormalizeOptions {
+            strip_markdown: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(options, "**bold** *italic* __under__ ~~gone~~ ***both***"),
+            "bold italic under gone both"
+        );
+        assert_eq!(
+            normalize(options, "# Title\n> quoted\n-# small"),
+            "Title\nquoted\nsmall"
+        );
+        assert_eq!(
+            normalize(options, "see [the docs](https://example.com)"),
+            "see the docs"
+        );
+        assert_eq!(
+            normalize(options, "_italic_ but snake_case_name stays"),
+            "italic but snake_case_name stays"
+        );
+        assert_eq!(normalize(options, "2 * 3 * 4"), "2 * 3 * 4");
+    }
+
+    #[test]
+    fn code_is_left_alone() {
+        assert_eq!(
+            normalize(all(), "**a** `**b** <:x:1>` ||c||"),
+            "a `**b** <:x:1>` c"
+        );
+        let block = "```rust\nlet x = **y**; // ||z|| <t:1700000000>\n```";
+        assert_eq!(
+            normalize(all(), &format!("~~a~~\n{block}")),
+            format!("a\n{block}")
+        );
+        assert_eq!(normalize(all(), "``a `<:x:1>` b``"), "``a `<:x:1>` b``");
+    }
+}
@@ -0,0 +1,166 @@
+//! Writing datasets incrementally.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+/// How records are laid out in the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// One JSON object per line.
+    #[default]
+    JsonLines,
+    /// A single JSON array.
+    Json,
+}
+
+impl Format {
+    /// The file extension conventionally used for this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::JsonLines => "jsonl",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::JsonLines),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown format {s:?}, expected one of: jsonl, json"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::JsonLines => "jsonl",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// What each record in a dataset describes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RecordKind {
+    /// `{prompt, reply}` objects built by [`get_prompt`](crate::get_prompt).
+    #[default]
+    Pairs,
+    /// `{turns: [{role, author, content}]}` objects built by
+    /// [`get_conversation`](crate::get_conversation).
+    Conversations,
+    /// `{messages: [{role, content}]}` objects in the OpenAI/ChatML
+    /// fine-tuning layout, built from the same pairs as
+    /// [`RecordKind::Pairs`].
+    ChatMl {
+        /// Sent as a leading `system` message when set.
+        system_prompt: Option<String>,
+    },
+    /// `{reply}` objects for every message by the target user, with no
+    /// prompt.
+    Monologue,
+}
+
+impl FromStr for RecordKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pairs" => Ok(Self::Pairs),
+            "conversations" => Ok(Self::Conversations),
+            "chatml" => Ok(Self::ChatMl {
+                system_prompt: None,
+            }),
+            "monologue" => Ok(Self::Monologue),
+            _ => Err(format!(
+                "unknown record kind {s:?}, expected one of: pairs, conversations, chatml, monologue"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RecordKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pairs => "pairs",
+            Self::Conversations => "conversations",
+            Self::ChatMl { .. } => "chatml",
+            Self::Monologue => "monologue",
+        })
+    }
+}
+
+/// Writes records one at a time, so a dataset never has to be held in memory.
+///
+/// Call [`DatasetWriter::finish`] once done; for [`Format::Json`] the array is
+/// not closed until then.
+pub struct DatasetWriter<W: Write> {
+    inner: W,
+    format: Format,
+    written: usize,
+}
+
+impl<W: Write> DatasetWriter<W> {
+    pub fn new(inner: W, format: Format) -> Self {
+        Self::resume(inner, format, 0)
+    }
+
+    /// Carry on a dataset that already holds `written` records, such as a file
+    /// reopened for appending. The dataset must not have been finished.
+    pub fn resume(inner: W, format: Format, written: usize) -> Self {
+        Self {
+            inner,
+            format,
+            written,
+        }
+    }
+
+    /// Append a single record.
+    pub fn write<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        match self.format {
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.inner, record)?;
+                self.inner.write_all(b"\n")?;
+            }
+            Format::Json => {
+                self.inner
+                    .write_all(if self.written == 0 { b"[" } else { b"," })?;
+                serde_json::to_writer(&mut self.inner, record)?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Flush buffered records through to the underlying writer, so that
+    /// everything written so far survives an interrupted run.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// How many records have been written.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Terminate the output and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == Format::Json {
+            self.inner
+                .write_all(if self.written == 0 { b"[]" } else { b"]" })?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use parsediscordarchive::{
+    ChannelInfo, DatasetWriter, Entry, Error, Format, Pseudonymizer, RedactKind, Redactions,
+};
+
+/// Which authors get a dataset.
+pub enum Selection {
+    /// Exactly these users, each written to the paired path.
+    Users(Vec<(u64, PathBuf)>),
+    /// Everyone with at least `min_messages` messages, written into `dir`.
+    All { min_messages: usize, dir: PathBuf },
+}
+
+/// Most dataset files kept open at once. The rest are closed, and reopened for
+/// appending when they are next written to.
+const OPEN_FILES: usize = 64;
+
+/// One dataset file per selected author.
+///
+/// With [`Selection::All`], whether an author has enough messages is only
+/// known at the end, so every author's records are written as they come and
+/// the datasets of those left short are deleted by [`Outputs::finish`].
+pub struct Outputs {
+    selection: Selection,
+    format: Format,
+    pseudonymizer: Option<Pseudonymizer>,
+    users: HashMap<u64, UserOutput>,
+    /// Users whose dataset is open, least recently written first.
+    open: VecDeque<u64>,
+}
+
+struct UserOutput {
+    path: PathBuf,
+    messages: usize,
+    /// Whether the file exists yet.
+    created: bool,
+    /// Records written so far, kept while the file is closed.
+    written: usize,
+    writer: Option<DatasetWriter<BufWriter<File>>>,
+}
+
+impl UserOutput {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            messages: 0,
+            created: false,
+            written: 0,
+            writer: None,
+        }
+    }
+
+    /// Flush and close the file, keeping its dataset unfinished.
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(mut writer) = self.writer.take() {
+            self.written = writer.written();
+            writer.flush().map_err(|e| output_error(&self.path, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Outputs {
+    pub fn new(
+        selection: Selection,
+        format: Format,
+        pseudonymizer: Option<Pseudonymizer>,
+    ) -> Result<Self, Error> {
+        let mut outputs = Self {
+            selection,
+            format,
+            pseudonymizer,
+            users: HashMap::new(),
+            open: VecDeque::new(),
+        };
+        if let Selection::Users(list) = &outputs.selection {
+            let list: Vec<(u64, PathBuf)> = list.clone();
+            for (user, path) in list {
+                outputs.users.insert(user, UserOutput::new(path));
+                // Created straight away, so every selected user gets a file.
+                outputs.open(user)?;
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Whether pairs authored by `user` should be built at all.
+    pub fn wants(&self, user: u64) -> bool {
+        match self.selection {
+            Selection::Users(_) => self.users.contains_key(&user),
+            Selection::All { .. } => true,
+        }
+    }
+
+    /// Note that `user` wrote another message.
+    pub fn count_message(&mut self, user: u64) {
+        if self.wants(user) {
+            self.output(user).messages += 1;
+        }
+    }
+
+    pub fn write(&mut self, user: u64, entry: Entry) -> Result<(), Error> {
+        self.open(user)?;
+        let output = self.users.get_mut(&user).unwrap();
+        let writer = output.writer.as_mut().unwrap();
+        writer
+            .write(&entry)
+            .map_err(|e| output_error(&output.path, e))
+    }
+
+    /// The output for `user`, added with its default path if it is new.
+    fn output(&mut self, user: u64) -> &mut UserOutput {
+        let Self {
+            users,
+            selection,
+            format,
+            pseudonymizer,
+            ..
+        } = self;
+        users.entry(user).or_insert_with(|| {
+            let Selection::All { dir, .. } = selection else {
+                unreachable!("explicitly selected users are added up front");
+            };
+            UserOutput::new(dir.join(file_name(user, *format, pseudonymizer.as_ref())))
+        })
+    }
+
+    /// Make sure `user`'s file is open, creating or reopening it, and closing
+    /// the least recently written one if too many are open.
+    fn open(&mut self, user: u64) -> Result<(), Error> {
+        if self.output(user).writer.is_some() {
+            if let Some(position) = self.open.iter().position(|v| *v == user) {
+                self.open.remove(position);
+            }
+        } else {
+            if self.open.len() >= OPEN_FILES {
+                let oldest = self.open.pop_front().unwrap();
+                self.users.get_mut(&oldest).unwrap().close()?;
+            }
+            let format = self.format;
+            let output = self.output(user);
+            let file = if output.created {
+                append(&output.path)?
+            } else {
+                create(&output.path)?
+            };
+            output.created = true;
+            output.writer = Some(DatasetWriter::resume(file, format, output.written));
+        }
+        self.open.push_back(user);
+        Ok(())
+    }
+
+    /// Push everything written so far through to disk.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        for output in self.users.values_mut() {
+            if let Some(writer) = &mut output.writer {
+                writer.flush().map_err(|e| output_error(&output.path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close every dataset, returning each user's output path and record count
+    /// sorted by user id. With [`Selection::All`], the datasets of authors
+    /// with too few messages are deleted instead.
+    pub fn finish(mut self) -> Result<Vec<(u64, PathBuf, usize)>, Error> {
+        let min_messages = match self.selection {
+            Selection::Users(_) => 0,
+            Selection::All { min_messages, .. } => min_messages,
+        };
+        let mut users: Vec<u64> = self.users.keys().copied().collect();
+        users.sort();
+        let mut written = Vec::new();
+        for user in users {
+            let output = &self.users[&user];
+            if !output.created {
+                continue;
+            }
+            if output.messages < min_messages {
+                let output = self.users.get_mut(&user).unwrap();
+                output.writer = None;
+                fs::remove_file(&output.path).map_err(|e| output_error(&output.path, e))?;
+                continue;
+            }
+            self.open(user)?;
+            let output = self.users.remove(&user).unwrap();
+            let writer = output.writer.unwrap();
+            let count = writer.written();
+            writer.finish().map_err(|e| output_error(&output.path, e))?;
+            if let Some(position) = self.open.iter().position(|v| *v == user) {
+                self.open.remove(position);
+            }
+            written.push((user, output.path, count));
+        }
+        Ok(written)
+    }
+}
+
+/// How `user` is named in output, their id or else its pseudonym.
+pub fn label(user: u64, pseudonymizer: Option<&Pseudonymizer>) -> String {
+    match pseudonymizer {
+        Some(pseudonymizer) => pseudonymizer.label(user),
+        None => user.to_string(),
+    }
+}
+
+/// The default name for `user`'s dataset.
+pub fn file_name(user: u64, format: Format, pseudonymizer: Option<&Pseudonymizer>) -> String {
+    format!(
+        "prompt-{}.{}",
+        label(user, pseudonymizer),
+        format.extension()
+    )
+}
+
+/// A CSV with a row for every file, counting what was redacted from it.
+pub struct RedactionReport {
+    path: PathBuf,
+    writer: csv::Writer<BufWriter<File>>,
+}
+
+impl RedactionReport {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let mut report = Self {
+            path: path.to_owned(),
+            writer: csv::Writer::from_writer(create(path)?),
+        };
+        let header = ["file", "guild_id", "channel_id", "thread_id"]
+            .map(str::to_owned)
+            .into_iter()
+            .chain(RedactKind::ALL.map(|v| v.to_string()));
+        report.write_record(header)?;
+        Ok(report)
+    }
+
+    pub fn write(
+        &mut self,
+        file: &Path,
+        info: &ChannelInfo,
+        redactions: &Redactions,
+    ) -> Result<(), Error> {
+        let id = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        let record = [
+            file.to_string_lossy().into_owned(),
+            id(info.guild_id),
+            id(info.channel_id),
+            id(info.thread_id),
+        ]
+        .into_iter()
+        .chain(RedactKind::ALL.map(|v| redactions.get(v).to_string()));
+        self.write_record(record)
+    }
+
+    fn write_record(&mut self, record: impl IntoIterator<Item = String>) -> Result<(), Error> {
+        self.writer
+            .write_record(record)
+            .map_err(|e| output_error(&self.path, e.into()))
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.writer.flush().map_err(|e| output_error(&self.path, e))
+    }
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, Error> {
+    OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)
+        .map(BufWriter::new)
+        .map_err(|e| output_error(path, e))
+}
+
+fn append(path: &Path) -> Result<BufWriter<File>, Error> {
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map(BufWriter::new)
+        .map_err(|e| output_error(path, e))
+}
+
+fn output_error(path: &Path, source: std::io::Error) -> Error {
+    Error::Output {
+        path: path.to_owned(),
+        source,
+    }
+}
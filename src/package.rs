@@ -0,0 +1,277 @@
+//! Reading Discord's official data package ("Request my data").
+//!
+//! A package holds a `messages` directory with one `c<channel id>` directory
+//! per channel, each with a `channel.json` and either a `messages.json` or an
+//! older `messages.csv`. Only messages sent by the package owner are included,
+//! so datasets built from a package are usually
+//! [monologues](crate::RecordKind::Monologue).
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+
+use crate::{
+    discover::walkdir,
+    meta::layout_info,
+    model::{Attachment, Channel, Media, Message},
+    Error,
+};
+
+/// One row of `messages.json` or `messages.csv`.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct PackageMessage {
+    #[serde(rename = "ID")]
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    pub id: u64,
+    #[serde(rename = "Timestamp", deserialize_with = "package_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "Contents", default)]
+    pub contents: String,
+    /// Space separated attachment URLs.
+    #[serde(rename = "Attachments", default)]
+    pub attachments: String,
+}
+
+impl PackageMessage {
+    /// Convert to a [`Message`] authored by the package owner.
+    pub fn into_message(self, owner: u64) -> Message {
+        Message {
+            id: self.id,
+            content: self.contents,
+            timestamp: self.timestamp,
+            author: owner,
+            reference: None,
+            media: Media {
+                attachments: self
+                    .attachments
+                    .split_whitespace()
+                    .map(|url| Attachment {
+                        filename: url_file_name(url).to_owned(),
+                        url: Some(url.to_owned()),
+                        content_type: None,
+                        size: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// The last path segment of an attachment URL, without any query string.
+fn url_file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// `account/user.json`, which identifies the package owner.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct PackageUser {
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    id: u64,
+}
+
+/// Packages have written timestamps as `2021-03-04 05:06:07.123000+00:00`, as a
+/// bare `2023-01-01 12:00:00` in UTC, and as RFC 3339.
+fn package_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<Utc>, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    DateTime::parse_from_str(&raw, "%Y-%m-%d %H:%M:%S%.f%:z")
+        .map(|v| v.to_utc())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&raw, "%Y-%m-%d %H:%M:%S%.f").map(|v| v.and_utc())
+        })
+        .or_else(|_| DateTime::parse_from_rfc3339(&raw).map(|v| v.to_utc()))
+        .map_err(serde::de::Error::custom)
+}
+
+/// The `messages` directory of a package rooted at `root_path`, which may
+/// also point at the `messages` directory itself.
+fn messages_dir(root_path: &Path) -> PathBuf {
+    let nested = root_path.join("messages");
+    if nested.is_dir() {
+        nested
+    } else {
+        root_path.to_owned()
+    }
+}
+
+/// Find the messages file of every channel in a data package, preferring
+/// `messages.json` over `messages.csv` where both exist.
+pub fn package_files(root_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut channel_dirs = walkdir(&messages_dir(root_path))?;
+    channel_dirs.retain(|v| v.is_dir());
+    channel_dirs.sort();
+    let mut files = Vec::with_capacity(channel_dirs.len());
+    for dir in channel_dirs {
+        let json = dir.join("messages.json");
+        let csv = dir.join("messages.csv");
+        if json.exists() {
+            files.push(json);
+        } else if csv.exists() {
+            files.push(csv);
+        } else {
+            eprintln!("Found no messages.json or messages.csv in {dir:?}, skipping..");
+        }
+    }
+    Ok(files)
+}
+
+/// Read the package owner's user id from `account/user.json`.
+///
+/// Returns `None` if the package has no `account` directory, which is the case
+/// when only the `messages` directory was kept.
+pub fn package_owner(root_path: &Path) -> Result<Option<u64>, Error> {
+    let path = root_path.join("account").join("user.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut data = fs::read(&path).map_err(|source| Error::Open {
+        path: path.clone(),
+        source,
+    })?;
+    let user: PackageUser =
+        simd_json::from_slice(&mut data).map_err(|source| Error::Parse { path, source })?;
+    Ok(Some(user.id))
+}
+
+/// Parse one package `messages.json` or `messages.csv`, attributing every
+/// message to `owner` and returning them oldest first.
+pub fn load_package_channel(path: &Path, owner: u64) -> Result<Channel, Error> {
+    let data = fs::read(path).map_err(|source| Error::Open {
+        path: path.to_owned(),
+        source,
+    })?;
+    let rows = parse_rows(path, data)?;
+    let mut messages: Vec<Message> = rows.into_iter().map(|v| v.into_message(owner)).collect();
+    messages.sort_by_key(|v| v.timestamp);
+    let info = layout_info(path, |v| fs::read(v).ok());
+    Ok(Channel { info, messages })
+}
+
+/// Parse the contents of a messages file, as CSV if `path` ends in `.csv` and
+/// as JSON otherwise.
+fn parse_rows(path: &Path, mut data: Vec<u8>) -> Result<Vec<PackageMessage>, Error> {
+    if path.extension().is_some_and(|ext| ext == "csv") {
+        csv::Reader::from_reader(data.as_slice())
+            .into_deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|source| Error::ParseCsv {
+                path: path.to_owned(),
+                source,
+            })
+    } else {
+        simd_json::from_slice(&mut data).map_err(|source| Error::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(file_name: &str, data: &str) -> Vec<PackageMessage> {
+        parse_rows(Path::new(file_name), data.as_bytes().to_vec()).unwrap()
+    }
+
+    fn utc(raw: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(raw).unwrap().to_utc()
+    }
+
+    #[test]
+    fn timestamps_with_offset() {
+        let rows = rows(
+            "messages.json",
+            r#"[{"ID": 1, "Timestamp": "2021-03-04 05:06:07.123000+02:00"}]"#,
+        );
+        assert_eq!(rows[0].timestamp, utc("2021-03-04T03:06:07.123Z"));
+    }
+
+    #[test]
+    fn timestamps_without_offset_are_utc() {
+        let rows = rows(
+            "messages.json",
+            r#"[{"ID": 1, "Timestamp": "2023-01-01 12:00:00"}]"#,
+        );
+        assert_eq!(rows[0].timestamp, utc("2023-01-01T12:00:00Z"));
+    }
+
+    #[test]
+    fn timestamps_in_rfc3339() {
+        let rows = rows(
+            "messages.json",
+            r#"[{"ID": 1, "Timestamp": "2024-05-06T07:08:09.5+00:00"}]"#,
+        );
+        assert_eq!(rows[0].timestamp, utc("2024-05-06T07:08:09.5Z"));
+    }
+
+    #[test]
+    fn bad_timestamps_fail() {
+        let result = parse_rows(
+            Path::new("messages.json"),
+            br#"[{"ID": 1, "Timestamp": "yesterday"}]"#.to_vec(),
+        );
+        assert!(matches!(result, Err(Error::Parse { .. })));
+    }
+
+    #[test]
+    fn json_rows() {
+        let rows = rows(
+            "messages.json",
+            r#"[
+                {"ID": 1, "Timestamp": "2023-01-01 12:00:00", "Contents": "hello", "Attachments": ""},
+                {"ID": "2", "Timestamp": "2023-01-01 12:01:00"}
+            ]"#,
+        );
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].id, rows[0].contents.as_str()), (1, "hello"));
+        // Ids may be strings, and contents and attachments may be missing.
+        assert_eq!((rows[1].id, rows[1].contents.as_str()), (2, ""));
+    }
+
+    #[test]
+    fn csv_rows_with_quoted_commas() {
+        let rows = rows(
+            "messages.csv",
+            "ID,Timestamp,Contents,Attachments\n\
+             1,2023-01-01 12:00:00,\"well, maybe\",\n\
+             2,2023-01-01 12:01:00,\"said \"\"no\"\"\",\n",
+        );
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].contents, "well, maybe");
+        assert_eq!(rows[0].attachments, "");
+        assert_eq!(rows[1].contents, "said \"no\"");
+    }
+
+    #[test]
+    fn attachments_are_named_without_query_strings() {
+        let rows = rows(
+            "messages.csv",
+            "ID,Timestamp,Contents,Attachments\n\
+             1,2023-01-01 12:00:00,look,\
+             https://cdn.discordapp.com/attachments/1/2/cat.png?ex=65a1&is=65b2&hm=ab12 \
+             https://cdn.discordapp.com/attachments/1/3/notes.txt#top\n",
+        );
+        let message = rows.into_iter().next().unwrap().into_message(42);
+        assert_eq!(message.author, 42);
+        let attachments = &message.media.attachments;
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].filename, "cat.png");
+        assert_eq!(
+            attachments[0].url.as_deref(),
+            Some("https://cdn.discordapp.com/attachments/1/2/cat.png?ex=65a1&is=65b2&hm=ab12")
+        );
+        assert_eq!(attachments[1].filename, "notes.txt");
+    }
+}
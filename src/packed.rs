@@ -0,0 +1,618 @@
+//! Reading archives that are still packed into a `.zip` or tarball, without
+//! extracting them to disk first.
+//!
+//! Message files inside the archive are addressed by joining their entry name
+//! onto the archive's path, so `server.zip/123/channel_messages.json` names
+//! the `123/channel_messages.json` entry of `server.zip`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::{discover::is_named, load::parse_channel, meta::layout_info, Error, LoadResult};
+
+/// The container formats [`PackedArchive`] can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackedKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl PackedKind {
+    /// Guess the container format from a file name, returning `None` for
+    /// anything that is not a recognised archive.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether an entry name is a `channel_messages.json` or a
+/// `threads/*/thread_messages.json`, possibly compressed on its own.
+fn is_messages_file(name: &str) -> bool {
+    let mut parts = name.rsplit('/');
+    match parts.next() {
+        Some(file) if is_named(file, "channel_messages.json") => true,
+        Some(file) if is_named(file, "thread_messages.json") => parts.nth(1) == Some("threads"),
+        _ => false,
+    }
+}
+
+/// Whether an entry name is a `channel.json` or `thread.json` describing the
+/// messages next to it.
+fn is_meta_file(name: &str) -> bool {
+    matches!(
+        name.rsplit('/').next(),
+        Some("channel.json" | "thread.json")
+    )
+}
+
+/// An archive packed into a single file.
+///
+/// Listing a tarball means reading all of it, so open each archive once and
+/// reuse it, even to load its files more than once.
+pub struct PackedArchive {
+    path: PathBuf,
+    files: Vec<PathBuf>,
+    /// The entry name behind each of `files`.
+    names: HashMap<PathBuf, String>,
+    /// The contents of every metadata file, by virtual path. These are small,
+    /// so they are read up front while listing.
+    meta: HashMap<PathBuf, Vec<u8>>,
+    reader: Reader,
+}
+
+enum Reader {
+    /// Idle handles to the zip file, so each worker can read its own entry.
+    Zip(Mutex<Vec<ZipArchive<File>>>),
+    Tar {
+        kind: PackedKind,
+        /// How many copies of each message file the tarball holds.
+        copies: HashMap<String, usize>,
+        /// The stream for the current pass over the tarball, started on first
+        /// use.
+        stream: Mutex<Option<Arc<TarStream>>>,
+    },
+}
+
+impl PackedArchive {
+    /// Open the archive at `path` and list the message files inside it.
+    pub fn open(path: &Path, kind: PackedKind) -> Result<Self, Error> {
+        let read_error = |source| Error::ReadArchive {
+            path: path.to_owned(),
+            source,
+        };
+        let mut meta = HashMap::new();
+        let mut names = Vec::new();
+        let mut copies = HashMap::new();
+        let reader = match kind {
+            PackedKind::Zip => {
+                let mut zip = open_zip(path)?;
+                for index in 0..zip.len() {
+                    let mut entry = zip.by_index(index).map_err(|e| read_error(e.into()))?;
+                    if !entry.is_file() {
+                        continue;
+                    }
+                    if is_messages_file(entry.name()) {
+                        add_name(&mut names, &mut copies, entry.name().to_owned());
+                    } else if is_meta_file(entry.name()) {
+                        let mut data = Vec::new();
+                        entry.read_to_end(&mut data).map_err(read_error)?;
+                        meta.insert(path.join(entry.name()), data);
+                    }
+                }
+                Reader::Zip(Mutex::new(vec![zip]))
+            }
+            kind => {
+                let mut tar = open_tar(path, kind)?;
+                for entry in tar.entries().map_err(read_error)? {
+                    let mut entry = entry.map_err(read_error)?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let name = entry.path().map_err(read_error)?;
+                    let name = name.to_string_lossy().into_owned();
+                    if is_messages_file(&name) {
+                        add_name(&mut names, &mut copies, name);
+                    } else if is_meta_file(&name) {
+                        let mut data = Vec::new();
+                        entry.read_to_end(&mut data).map_err(read_error)?;
+                        meta.insert(path.join(&name), data);
+                    }
+                }
+                Reader::Tar {
+                    kind,
+                    copies,
+                    stream: Mutex::new(None),
+                }
+            }
+        };
+        let files: Vec<PathBuf> = names.iter().map(|name| path.join(name)).collect();
+        Ok(Self {
+            path: path.to_owned(),
+            names: files.iter().cloned().zip(names).collect(),
+            files,
+            meta,
+            reader,
+        })
+    }
+
+    /// Every message file in the archive, in the order they are stored.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Read and parse one of [`PackedArchive::files`].
+    ///
+    /// Tarballs can only be read front to back, so files must be requested
+    /// roughly in the order [`PackedArchive::files`] lists them, as
+    /// [`for_each_channel`](crate::for_each_channel) does. Asking for a file
+    /// that has already been read starts over from the front.
+    pub fn load(&self, path: &Path) -> LoadResult {
+        let read_error = |source| Error::ReadArchive {
+            path: path.to_owned(),
+            source,
+        };
+        let name = self
+            .names
+            .get(path)
+            .ok_or_else(|| read_error(not_found()))?;
+        let data = match &self.reader {
+            Reader::Zip(pool) => {
+                let idle = pool.lock().unwrap().pop();
+                let mut zip = match idle {
+                    Some(zip) => zip,
+                    None => open_zip(&self.path)?,
+                };
+                let mut data = Vec::new();
+                let read = zip
+                    .by_name(name)
+                    .map_err(io::Error::from)
+                    .and_then(|mut entry| entry.read_to_end(&mut data));
+                pool.lock().unwrap().push(zip);
+                read.map_err(read_error)?;
+                data
+            }
+            Reader::Tar {
+                kind,
+                copies,
+                stream,
+            } => {
+                let stream = {
+                    let mut current = stream.lock().unwrap();
+                    match &*current {
+                        Some(stream) if !stream.has_taken(name) => Arc::clone(stream),
+                        // Either the first file asked for, or a new pass over
+                        // the archive.
+                        _ => {
+                            let (path, kind) = (self.path.clone(), *kind);
+                            let open = move || {
+                                open_tar(&path, kind).map_err(|e| io::Error::other(e.to_string()))
+                            };
+                            let stream = Arc::new(TarStream::spawn(open, copies.clone()));
+                            *current = Some(Arc::clone(&stream));
+                            stream
+                        }
+                    }
+                };
+                stream.take(name).map_err(read_error)?
+            }
+        };
+        let mut channel = parse_channel(path, data)?;
+        let info = layout_info(path, |v| self.meta.get(v).cloned());
+        channel.info = channel.info.or(info);
+        Ok(channel)
+    }
+}
+
+/// Add a message file to the listing. Only the last copy of a duplicated entry
+/// is kept, as when unpacking the archive, so it is listed where that copy is.
+fn add_name(names: &mut Vec<String>, copies: &mut HashMap<String, usize>, name: String) {
+    let count = copies.entry(name.clone()).or_default();
+    if *count > 0 {
+        names.retain(|v| *v != name);
+    }
+    *count += 1;
+    names.push(name);
+}
+
+fn open_zip(path: &Path) -> Result<ZipArchive<File>, Error> {
+    let file = File::open(path).map_err(|source| Error::Open {
+        path: path.to_owned(),
+        source,
+    })?;
+    ZipArchive::new(file).map_err(|e| Error::ReadArchive {
+        path: path.to_owned(),
+        source: e.into(),
+    })
+}
+
+fn open_tar(path: &Path, kind: PackedKind) -> Result<tar::Archive<Box<dyn Read>>, Error> {
+    let open_error = |source| Error::Open {
+        path: path.to_owned(),
+        source,
+    };
+    let file = BufReader::new(File::open(path).map_err(open_error)?);
+    let reader: Box<dyn Read> = match kind {
+        PackedKind::TarGz => Box::new(GzDecoder::new(file)),
+        PackedKind::TarZst => Box::new(zstd::Decoder::with_buffer(file).map_err(open_error)?),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file in archive")
+}
+
+/// Reads a tarball front to back on a background thread, holding each wanted
+/// entry until a worker takes it.
+struct TarStream {
+    shared: Arc<(Mutex<TarState>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct TarState {
+    ready: HashMap<String, Vec<u8>>,
+    /// Every entry handed out so far.
+    taken: HashSet<String>,
+    /// Set once the reader thread has stopped.
+    done: bool,
+    /// Set when the archive is dropped, to stop the reader thread early.
+    closed: bool,
+    /// Why the reader thread stopped before the end of the archive.
+    error: Option<(io::ErrorKind, String)>,
+}
+
+impl TarStream {
+    /// Start reading the tarball returned by `open`, keeping the last of the
+    /// given number of copies of each entry in `wanted`.
+    fn spawn(
+        open: impl FnOnce() -> io::Result<tar::Archive<Box<dyn Read>>> + Send + 'static,
+        wanted: HashMap<String, usize>,
+    ) -> Self {
+        let shared = Arc::new((Mutex::new(TarState::default()), Condvar::new()));
+        // Enough for each worker to have an entry waiting without letting the
+        // reader run arbitrarily far ahead.
+        let capacity = thread::available_parallelism().map_or(1, |v| v.get());
+        let handle = thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || {
+                let (state, changed) = &*shared;
+                let result = open().and_then(|tar| {
+                    read_tar(tar, &wanted, |name, data| {
+                        let state = state.lock().unwrap();
+                        let mut state = changed
+                            .wait_while(state, |v| v.ready.len() >= capacity && !v.closed)
+                            .unwrap();
+                        if state.closed {
+                            return false;
+                        }
+                        state.ready.insert(name, data);
+                        changed.notify_all();
+                        true
+                    })
+                });
+                let mut state = state.lock().unwrap();
+                state.done = true;
+                state.error = result.err().map(|e| (e.kind(), e.to_string()));
+                changed.notify_all();
+            }
+        });
+        Self {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Wait for the entry called `name` to be read.
+    fn take(&self, name: &str) -> io::Result<Vec<u8>> {
+        let (state, changed) = &*self.shared;
+        let state = state.lock().unwrap();
+        let mut state = changed
+            .wait_while(state, |v| !v.ready.contains_key(name) && !v.done)
+            .unwrap();
+        if let Some(data) = state.ready.remove(name) {
+            state.taken.insert(name.to_owned());
+            changed.notify_all();
+            return Ok(data);
+        }
+        Err(match &state.error {
+            Some((kind, message)) => io::Error::new(*kind, message.clone()),
+            None => not_found(),
+        })
+    }
+
+    /// Whether the entry called `name` has already been handed out, so that
+    /// reading it again needs a new stream.
+    fn has_taken(&self, name: &str) -> bool {
+        self.shared.0.lock().unwrap().taken.contains(name)
+    }
+}
+
+impl Drop for TarStream {
+    fn drop(&mut self) {
+        let (state, changed) = &*self.shared;
+        state.lock().unwrap().closed = true;
+        changed.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Read the last copy of every `wanted` entry of a tarball, handing each to
+/// `deliver` until it returns `false`.
+fn read_tar(
+    mut tar: tar::Archive<Box<dyn Read>>,
+    wanted: &HashMap<String, usize>,
+    mut deliver: impl FnMut(String, Vec<u8>) -> bool,
+) -> io::Result<()> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let Some(&copies) = wanted.get(&name) else {
+            continue;
+        };
+        let count = seen.entry(name.clone()).or_default();
+        *count += 1;
+        if *count < copies {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        if !deliver(name, data) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+
+    /// A tarball holding `entries`, with names written exactly as given
+    /// rather than cleaned up like [`tar::Builder`] does.
+    fn tarball(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            builder.append(&header, data.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Write `data` to a file in the temporary directory.
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("packed-{}-{name}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn messages(content: &str) -> String {
+        format!(
+            r#"[{{"id": "1", "timestamp": "2024-01-01T00:00:00+00:00",
+                 "content": "{content}", "author": {{"id": "100"}}}}]"#
+        )
+    }
+
+    fn stream(data: Vec<u8>, wanted: &[(&str, usize)]) -> TarStream {
+        let wanted = wanted.iter().map(|&(k, v)| (k.to_owned(), v)).collect();
+        TarStream::spawn(
+            move || {
+                Ok(tar::Archive::new(
+                    Box::new(Cursor::new(data)) as Box<dyn Read>
+                ))
+            },
+            wanted,
+        )
+    }
+
+    /// Reads like the wrapped reader until it runs out, then fails.
+    struct FailsAtEnd(Cursor<Vec<u8>>);
+
+    impl Read for FailsAtEnd {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::InvalidData, "disk on fire")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn messages_files() {
+        for name in [
+            "123/channel_messages.json",
+            "./123/channel_messages.json",
+            "./export/./123/channel_messages.json.gz",
+            "123/threads/456/thread_messages.json",
+            "./123/threads/456/thread_messages.json.zst",
+            "channel_messages.json",
+        ] {
+            assert!(is_messages_file(name), "{name}");
+        }
+        for name in [
+            "123/thread_messages.json",
+            "123/threads/thread_messages.json",
+            "123/channel_messages.json.bak",
+            "123/channel.json",
+            "./123/threads/456/thread.json",
+        ] {
+            assert!(!is_messages_file(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn stream_hands_out_entries_in_order() {
+        let data = tarball(&[
+            ("a/channel_messages.json", "a"),
+            ("a/channel.json", "{}"),
+            ("b/channel_messages.json", "b"),
+        ]);
+        let stream = stream(
+            data,
+            &[
+                ("a/channel_messages.json", 1),
+                ("b/channel_messages.json", 1),
+            ],
+        );
+        assert_eq!(stream.take("a/channel_messages.json").unwrap(), b"a");
+        assert!(stream.has_taken("a/channel_messages.json"));
+        assert!(!stream.has_taken("b/channel_messages.json"));
+        assert_eq!(stream.take("b/channel_messages.json").unwrap(), b"b");
+        let missing = stream.take("a/channel.json").unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn stream_keeps_the_last_copy() {
+        let data = tarball(&[
+            ("a/channel_messages.json", "old"),
+            ("b/channel_messages.json", "b"),
+            ("a/channel_messages.json", "new"),
+        ]);
+        let stream = stream(
+            data,
+            &[
+                ("a/channel_messages.json", 2),
+                ("b/channel_messages.json", 1),
+            ],
+        );
+        assert_eq!(stream.take("b/channel_messages.json").unwrap(), b"b");
+        assert_eq!(stream.take("a/channel_messages.json").unwrap(), b"new");
+    }
+
+    #[test]
+    fn reader_errors_reach_take() {
+        let mut data = tarball(&[
+            ("a/channel_messages.json", "a"),
+            ("b/channel_messages.json", "b"),
+        ]);
+        // Cut the tarball off after the first entry's header and data block.
+        data.truncate(1024);
+        let stream = TarStream::spawn(
+            move || {
+                let reader = FailsAtEnd(Cursor::new(data));
+                Ok(tar::Archive::new(Box::new(reader) as Box<dyn Read>))
+            },
+            [
+                ("a/channel_messages.json".to_owned(), 1),
+                ("b/channel_messages.json".to_owned(), 1),
+            ]
+            .into(),
+        );
+        assert_eq!(stream.take("a/channel_messages.json").unwrap(), b"a");
+        let error = stream.take("b/channel_messages.json").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("disk on fire"), "{error}");
+    }
+
+    #[test]
+    fn dot_prefixed_names() {
+        let data = tarball(&[
+            ("./123/channel.json", r#"{"id": "123", "name": "general"}"#),
+            ("./123/channel_messages.json", &messages("channel")),
+            (
+                "./123/threads/456/thread_messages.json",
+                &messages("thread"),
+            ),
+        ]);
+        let path = temp_file("dot-prefixed.tar", &data);
+        let archive = PackedArchive::open(&path, PackedKind::Tar).unwrap();
+        let files = archive.files().to_vec();
+        assert_eq!(
+            files,
+            [
+                path.join("./123/channel_messages.json"),
+                path.join("./123/threads/456/thread_messages.json"),
+            ]
+        );
+        let channel = archive.load(&files[0]).unwrap();
+        assert_eq!(channel.messages[0].content, "channel");
+        assert_eq!(channel.info.channel_id, Some(123));
+        assert_eq!(channel.info.channel_name.as_deref(), Some("general"));
+        let thread = archive.load(&files[1]).unwrap();
+        assert_eq!(thread.messages[0].content, "thread");
+        assert_eq!(thread.info.channel_id, Some(123));
+        assert_eq!(thread.info.thread_id, Some(456));
+        drop(archive);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn duplicates_are_listed_once() {
+        let data = tarball(&[
+            ("a/channel_messages.json", &messages("old")),
+            ("b/channel_messages.json", &messages("b")),
+            ("a/channel_messages.json", &messages("new")),
+        ]);
+        let path = temp_file("duplicates.tar", &data);
+        let archive = PackedArchive::open(&path, PackedKind::Tar).unwrap();
+        let files = archive.files().to_vec();
+        assert_eq!(
+            files,
+            [
+                path.join("b/channel_messages.json"),
+                path.join("a/channel_messages.json"),
+            ]
+        );
+        assert_eq!(archive.load(&files[0]).unwrap().messages[0].content, "b");
+        assert_eq!(archive.load(&files[1]).unwrap().messages[0].content, "new");
+        drop(archive);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn loading_again_starts_over() {
+        let data = tarball(&[
+            ("a/channel_messages.json", &messages("a")),
+            ("b/channel_messages.json", &messages("b")),
+        ]);
+        let path = temp_file("again.tar", &data);
+        let archive = PackedArchive::open(&path, PackedKind::Tar).unwrap();
+        let files = archive.files().to_vec();
+        for _ in 0..2 {
+            assert_eq!(archive.load(&files[0]).unwrap().messages[0].content, "a");
+            assert_eq!(archive.load(&files[1]).unwrap().messages[0].content, "b");
+        }
+        // Stopping part way through a pass is fine too.
+        assert_eq!(archive.load(&files[0]).unwrap().messages[0].content, "a");
+        assert_eq!(archive.load(&files[0]).unwrap().messages[0].content, "a");
+        drop(archive);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,734 @@
+//! Pairing a user's messages with the context that prompted them.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    meta::ChannelInfo,
+    model::{Channel, ChatExample, Conversation, Message, Monologue, Record, Reply, Role, Turn},
+    output::RecordKind,
+};
+
+/// How [`get_prompt`] and [`get_conversation`] pick earlier messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextStrategy {
+    /// The messages just before the reply, or just before the message it
+    /// replies to.
+    #[default]
+    Recent,
+    /// The chain of messages the reply answers, following up to `max_depth`
+    /// reply references regardless of how far apart they are. Messages that
+    /// are not replies fall back to [`ContextStrategy::Recent`].
+    ReplyChain { max_depth: usize },
+    /// In threads, always start with the post that opened the thread,
+    /// optionally headed by the thread's title, followed by the messages just
+    /// before the reply. Elsewhere this is the same as
+    /// [`ContextStrategy::Recent`].
+    ///
+    /// The opening post counts towards [`ContextOptions::max_messages`]. It is
+    /// left out when the context comes from another channel, such as for a
+    /// reply to a message in the thread's parent.
+    ThreadStarter { include_title: bool },
+}
+
+/// Limits on how much earlier conversation [`get_prompt`] gathers.
+#[derive(Debug, Clone)]
+pub struct ContextOptions {
+    pub strategy: ContextStrategy,
+    /// Most messages to include.
+    pub max_messages: usize,
+    /// Messages further than this before the reply (or the message it
+    /// references) are not included.
+    pub max_gap: Duration,
+    /// Most characters the joined prompt may contain, if limited.
+    pub max_chars: Option<usize>,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            strategy: ContextStrategy::Recent,
+            max_messages: 5,
+            max_gap: Duration::minutes(10),
+            max_chars: None,
+        }
+    }
+}
+
+/// Build the prompt for the message at `index`, which should be authored by
+/// `who`.
+///
+/// If the message is a reply, context is gathered starting from the message
+/// it references; otherwise from the message just before it. Earlier messages
+/// are collected until `options` says to stop or a message by `who` is found.
+/// With [`ContextStrategy::ReplyChain`], the prompt is instead the chain of
+/// replies leading to the message, and with [`ContextStrategy::ThreadStarter`]
+/// it is preceded by the thread's opening post. Returns `None` if no context
+/// was found.
+pub fn get_prompt(
+    messages: &[Message],
+    ids: &MessageIndex,
+    index: usize,
+    who: u64,
+    options: &ContextOptions,
+) -> Option<String> {
+    if let ContextStrategy::ReplyChain { max_depth } = options.strategy {
+        let chain = reply_chain(messages, ids, index, max_depth);
+        if !chain.is_empty() {
+            let mut outputs: Vec<&str> = Vec::new();
+            let mut chars = 0;
+            for message in chain.iter().filter(|v| !v.content.is_empty()) {
+                let len = message.content.chars().count() + usize::from(!outputs.is_empty());
+                if options.max_chars.is_some_and(|max| chars + len > max) {
+                    break;
+                }
+                chars += len;
+                outputs.push(&message.content);
+            }
+            outputs.reverse();
+            return (!outputs.is_empty()).then(|| outputs.join("\n"));
+        }
+    }
+    let (context, start, reference_time) = context_start(messages, ids, index)?;
+    let opening = thread_opening(messages, ids, index, who, options, context)
+        .map(|(_, opening)| opening)
+        .filter(|v| options.max_chars.is_none_or(|max| v.chars().count() <= max));
+    let mut outputs: Vec<String> = Vec::new();
+    let mut chars = opening.as_ref().map_or(0, |v| v.chars().count());
+    // The opening post is added after, so don't walk back onto it.
+    let skip = usize::from(opening.is_some());
+    let max_messages = options.max_messages - skip;
+
+    for prompt in context[skip..=start].iter().rev() {
+        if outputs.len() >= max_messages
+            || prompt.author == who
+            || reference_time - prompt.timestamp > options.max_gap
+        {
+            break;
+        }
+        if prompt.content.is_empty() {
+            continue;
+        }
+        // Account for the newline joining this message to the next.
+        let len =
+            prompt.content.chars().count() + usize::from(!outputs.is_empty() || opening.is_some());
+        if options.max_chars.is_some_and(|max| chars + len > max) {
+            break;
+        }
+        chars += len;
+        outputs.push(prompt.content.clone());
+    }
+    outputs.extend(opening);
+    if outputs.is_empty() {
+        None
+    } else {
+        outputs.reverse();
+        Some(outputs.join("\n"))
+    }
+}
+
+/// Build the conversation leading up to the message at `index`, which should
+/// be authored by `who`.
+///
+/// Unlike [`get_prompt`], earlier messages by `who` are kept as
+/// [`Role::Assistant`] turns rather than ending the context, and the message
+/// itself is the final turn. The conversation always opens with a
+/// [`Role::User`] turn. Returns `None` if nobody else spoke first.
+pub fn get_conversation(
+    messages: &[Message],
+    ids: &MessageIndex,
+    index: usize,
+    who: u64,
+    options: &ContextOptions,
+) -> Option<Conversation> {
+    if let ContextStrategy::ReplyChain { max_depth } = options.strategy {
+        let chain = reply_chain(messages, ids, index, max_depth);
+        if !chain.is_empty() {
+            let mut turns = Vec::new();
+            let mut chars = 0;
+            for message in chain.into_iter().filter(|v| !v.is_empty()) {
+                let len = message.content.chars().count();
+                if options.max_chars.is_some_and(|max| chars + len > max) {
+                    break;
+                }
+                chars += len;
+                turns.push(Turn::new(message, who));
+            }
+            return finish_conversation(turns, &messages[index], who);
+        }
+    }
+    let (context, start, reference_time) = context_start(messages, ids, index)?;
+    let opening = thread_opening(messages, ids, index, who, options, context)
+        .filter(|(_, v)| options.max_chars.is_none_or(|max| v.chars().count() <= max));
+    let mut turns = Vec::new();
+    let mut chars = opening.as_ref().map_or(0, |(_, v)| v.chars().count());
+    // The opening post is added after, so don't walk back onto it.
+    let skip = usize::from(opening.is_some());
+    let max_messages = options.max_messages - skip;
+
+    for message in context[skip..=start].iter().rev() {
+        if turns.len() >= max_messages || reference_time - message.timestamp > options.max_gap {
+            break;
+        }
+        if message.is_empty() {
+            continue;
+        }
+        let len = message.content.chars().count();
+        if options.max_chars.is_some_and(|max| chars + len > max) {
+            break;
+        }
+        chars += len;
+        turns.push(Turn::new(message, who));
+    }
+    if let Some((starter, content)) = opening {
+        turns.push(Turn {
+            content,
+            ..Turn::new(starter, who)
+        });
+    }
+    finish_conversation(turns, &messages[index], who)
+}
+
+/// The post that opened the thread the message at `index` is in, and the text
+/// to open its context with, when using [`ContextStrategy::ThreadStarter`].
+///
+/// Returns `None` outside threads, for the opening post itself, and when
+/// `who` started the thread, as their own post can't prompt them. Also
+/// returns `None` when `context` is another channel, as the thread's opening
+/// post would then be out of order, and when `max_messages` leaves no room.
+fn thread_opening<'a>(
+    messages: &'a [Message],
+    ids: &MessageIndex,
+    index: usize,
+    who: u64,
+    options: &ContextOptions,
+    context: &[Message],
+) -> Option<(&'a Message, String)> {
+    let ContextStrategy::ThreadStarter { include_title } = options.strategy else {
+        return None;
+    };
+    if !std::ptr::eq(context, messages) || options.max_messages == 0 {
+        return None;
+    }
+    let info = ids.info.filter(|v| v.is_thread())?;
+    let starter = messages.first().filter(|v| index > 0 && v.author != who)?;
+    let title = info.thread_name.as_deref().filter(|_| include_title);
+    let content = match title {
+        Some(title) if starter.content.is_empty() => title.to_owned(),
+        Some(title) => format!("{title}\n{}", starter.content),
+        None if starter.content.is_empty() => return None,
+        None => starter.content.clone(),
+    };
+    Some((starter, content))
+}
+
+/// Turn context gathered newest first into a conversation ending in `reply`,
+/// dropping turns by `who` from the start so it opens with someone else.
+fn finish_conversation(mut turns: Vec<Turn>, reply: &Message, who: u64) -> Option<Conversation> {
+    while turns
+        .last()
+        .is_some_and(|turn| turn.role == Role::Assistant)
+    {
+        turns.pop();
+    }
+    if turns.is_empty() {
+        return None;
+    }
+    turns.reverse();
+    turns.push(Turn::new(reply, who));
+    Some(Conversation { turns })
+}
+
+/// Follow reply references back from the message at `index`, returning up to
+/// `max_depth` messages newest first.
+fn reply_chain<'a>(
+    messages: &'a [Message],
+    ids: &MessageIndex<'a>,
+    index: usize,
+    max_depth: usize,
+) -> Vec<&'a Message> {
+    let mut chain = Vec::new();
+    let mut current = &messages[index];
+    while chain.len() < max_depth {
+        let Some(reference) = &current.reference else {
+            break;
+        };
+        let Some(next) = ids.resolve(messages, reference.message_id) else {
+            break;
+        };
+        // A reference can't point forward in time; treat one that does as
+        // broken rather than risk a loop.
+        if next.timestamp > current.timestamp || next.id == current.id {
+            break;
+        }
+        chain.push(next);
+        current = next;
+    }
+    chain
+}
+
+/// Where each message in a channel is, by id, so replies can be resolved
+/// without scanning the channel.
+#[derive(Debug, Default)]
+pub struct MessageIndex<'a> {
+    positions: HashMap<u64, usize>,
+    archive: Option<&'a ArchiveIndex<'a>>,
+    info: Option<&'a ChannelInfo>,
+}
+
+impl<'a> MessageIndex<'a> {
+    /// Index `messages`, which should be the channel passed alongside it to
+    /// [`get_prompt`] or [`get_conversation`].
+    pub fn new(messages: &[Message]) -> Self {
+        let positions = messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| (message.id, index))
+            .collect();
+        Self {
+            positions,
+            archive: None,
+            info: None,
+        }
+    }
+
+    /// Like [`MessageIndex::new`], but replies to messages outside the
+    /// channel are resolved through `archive`.
+    pub fn with_archive(messages: &[Message], archive: &'a ArchiveIndex<'a>) -> Self {
+        Self {
+            archive: Some(archive),
+            ..Self::new(messages)
+        }
+    }
+
+    /// Note where the channel came from, so [`ContextStrategy::ThreadStarter`]
+    /// can tell whether it is a thread.
+    pub fn with_info(self, info: &'a ChannelInfo) -> Self {
+        Self {
+            info: Some(info),
+            ..self
+        }
+    }
+
+    /// The position of the message with the given id.
+    pub fn position(&self, id: u64) -> Option<usize> {
+        self.positions.get(&id).copied()
+    }
+
+    /// The message with the given id, looking in `messages` (the indexed
+    /// channel) and then in the archive, if there is one.
+    pub fn resolve(&self, messages: &'a [Message], id: u64) -> Option<&'a Message> {
+        match self.position(id) {
+            Some(position) => messages.get(position),
+            None => {
+                let (channel, position) = self.archive?.get(id)?;
+                channel.get(position)
+            }
+        }
+    }
+}
+
+/// Where every message in a whole archive is, by id, so replies can be
+/// followed into other channels, such as from a thread to its parent.
+#[derive(Debug)]
+pub struct ArchiveIndex<'a> {
+    channels: &'a [Channel],
+    positions: HashMap<u64, (usize, usize)>,
+}
+
+impl<'a> ArchiveIndex<'a> {
+    pub fn new(channels: &'a [Channel]) -> Self {
+        let mut positions = HashMap::with_capacity(channels.iter().map(|v| v.messages.len()).sum());
+        for (channel_index, channel) in channels.iter().enumerate() {
+            for (index, message) in channel.messages.iter().enumerate() {
+                positions.insert(message.id, (channel_index, index));
+            }
+        }
+        Self {
+            channels,
+            positions,
+        }
+    }
+
+    /// The channel holding the message with the given id, and its position
+    /// in that channel.
+    pub fn get(&self, id: u64) -> Option<(&'a [Message], usize)> {
+        let &(channel, index) = self.positions.get(&id)?;
+        Some((&self.channels[channel].messages, index))
+    }
+}
+
+/// Find where context for the message at `index` starts: the message it
+/// replies to if that is earlier in `messages` or, with an [`ArchiveIndex`],
+/// anywhere else in the archive; otherwise the one just before it.
+///
+/// Returns the messages to take context from, the position to start at, and
+/// the time that context gaps are measured from.
+fn context_start<'a>(
+    messages: &'a [Message],
+    ids: &MessageIndex<'a>,
+    index: usize,
+) -> Option<(&'a [Message], usize, DateTime<Utc>)> {
+    let reply = &messages[index];
+    if let Some(reference) = &reply.reference {
+        let local = ids
+            .position(reference.message_id)
+            .filter(|&position| position + 1 < index);
+        if let Some(position) = local {
+            return Some((messages, position, messages[position].timestamp));
+        }
+        let remote = ids
+            .archive
+            .and_then(|archive| archive.get(reference.message_id))
+            .filter(|(context, position)| {
+                !std::ptr::eq(*context, messages) && context[*position].timestamp <= reply.timestamp
+            });
+        if let Some((context, position)) = remote {
+            return Some((context, position, context[position].timestamp));
+        }
+    }
+    (index > 0).then(|| (messages, index - 1, reply.timestamp))
+}
+
+/// Pair every non-empty message by `who` in a channel with its prompt.
+pub fn channel_replies(channel: &[Message], who: u64, options: &ContextOptions) -> Vec<Reply> {
+    channel_replies_by(channel, options, |author| author == who)
+        .into_iter()
+        .map(|(_, reply)| reply)
+        .collect()
+}
+
+/// Pair every non-empty message in a channel whose author passes `include`
+/// with its prompt, returning the author alongside each pair.
+///
+/// This lets datasets for many users be built from a single parse.
+pub fn channel_replies_by(
+    channel: &[Message],
+    options: &ContextOptions,
+    include: impl FnMut(u64) -> bool,
+) -> Vec<(u64, Reply)> {
+    let ids = MessageIndex::new(channel);
+    records_by(channel, &ids, &RecordKind::Pairs, options, include)
+        .into_iter()
+        .filter_map(|(author, record)| match record {
+            Record::Pair(reply) => Some((author, reply)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Build a record of the given kind for every non-empty message in a channel
+/// whose author passes `include`, returning the author alongside each record.
+///
+/// With an `archive`, replies to messages in other channels take their
+/// context from there.
+pub fn channel_records_by(
+    channel: &Channel,
+    archive: Option<&ArchiveIndex>,
+    kind: &RecordKind,
+    options: &ContextOptions,
+    include: impl FnMut(u64) -> bool,
+) -> Vec<(u64, Record)> {
+    let messages = &channel.messages;
+    let ids = match archive {
+        Some(archive) => MessageIndex::with_archive(messages, archive),
+        None => MessageIndex::new(messages),
+    };
+    records_by(
+        messages,
+        &ids.with_info(&channel.info),
+        kind,
+        options,
+        include,
+    )
+}
+
+fn records_by(
+    channel: &[Message],
+    ids: &MessageIndex,
+    kind: &RecordKind,
+    options: &ContextOptions,
+    mut include: impl FnMut(u64) -> bool,
+) -> Vec<(u64, Record)> {
+    let mut records = Vec::new();
+    for (index, message) in channel.iter().enumerate() {
+        if message.is_empty() || !include(message.author) {
+            continue;
+        }
+        let who = message.author;
+        let record = match kind {
+            RecordKind::Pairs => get_prompt(channel, ids, index, who, options).map(|prompt| {
+                Record::Pair(Reply {
+                    prompt,
+                    reply: message.content.clone(),
+                    media: message.media.clone(),
+                })
+            }),
+            // Chat examples have nowhere to put media, so need a text reply.
+            RecordKind::ChatMl { .. } if message.content.is_empty() => None,
+            RecordKind::ChatMl { system_prompt } => get_prompt(channel, ids, index, who, options)
+                .map(|prompt| {
+                    Record::Chat(ChatExample::new(
+                        system_prompt.as_deref(),
+                        prompt,
+                        message.content.clone(),
+                    ))
+                }),
+            RecordKind::Monologue => Some(Record::Monologue(Monologue {
+                reply: message.content.clone(),
+                media: message.media.clone(),
+            })),
+            RecordKind::Conversations => {
+                get_conversation(channel, ids, index, who, options).map(Record::Conversation)
+            }
+        };
+        if let Some(record) = record {
+            records.push((who, record));
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::model::Reference;
+
+    /// A message sent `minute` minutes into the day.
+    fn message(id: u64, author: u64, minute: i64, content: &str) -> Message {
+        Message {
+            id,
+            author,
+            content: content.to_owned(),
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+                + Duration::minutes(minute),
+            ..Default::default()
+        }
+    }
+
+    fn reply(message: Message, to: u64) -> Message {
+        Message {
+            reference: Some(Reference {
+                message_id: to,
+                channel_id: None,
+                guild_id: None,
+            }),
+            ..message
+        }
+    }
+
+    const ALICE: u64 = 1;
+    const BOB: u64 = 2;
+    const CAROL: u64 = 3;
+
+    fn prompt(messages: &[Message], ids: &MessageIndex, index: usize) -> Option<String> {
+        let who = messages[index].author;
+        get_prompt(messages, ids, index, who, &ContextOptions::default())
+    }
+
+    fn conversation(messages: &[Message], ids: &MessageIndex, index: usize) -> Option<Vec<String>> {
+        let who = messages[index].author;
+        let conversation = get_conversation(messages, ids, index, who, &ContextOptions::default())?;
+        Some(conversation.turns.into_iter().map(|v| v.content).collect())
+    }
+
+    #[test]
+    fn recent_messages() {
+        let messages = [
+            message(10, BOB, 0, "anyone around?"),
+            message(11, CAROL, 1, "yes"),
+            message(12, ALICE, 2, "me too"),
+        ];
+        let ids = MessageIndex::new(&messages);
+        assert_eq!(
+            prompt(&messages, &ids, 2).as_deref(),
+            Some("anyone around?\nyes")
+        );
+        assert_eq!(
+            conversation(&messages, &ids, 2).unwrap(),
+            ["anyone around?", "yes", "me too"]
+        );
+    }
+
+    #[test]
+    fn second_message_is_prompted_by_the_first() {
+        let messages = [message(10, BOB, 0, "hello"), message(11, ALICE, 1, "hi")];
+        let ids = MessageIndex::new(&messages);
+        assert_eq!(prompt(&messages, &ids, 1).as_deref(), Some("hello"));
+        assert_eq!(prompt(&messages, &ids, 0), None);
+    }
+
+    #[test]
+    fn local_reference() {
+        let messages = [
+            message(10, CAROL, 0, "earlier"),
+            message(11, BOB, 1, "question"),
+            message(12, CAROL, 2, "unrelated"),
+            reply(message(13, ALICE, 3, "answer"), 11),
+        ];
+        let ids = MessageIndex::new(&messages);
+        assert_eq!(
+            prompt(&messages, &ids, 3).as_deref(),
+            Some("earlier\nquestion")
+        );
+        assert_eq!(
+            conversation(&messages, &ids, 3).unwrap(),
+            ["earlier", "question", "answer"]
+        );
+    }
+
+    #[test]
+    fn reference_to_the_first_message() {
+        let messages = [
+            message(10, BOB, 0, "question"),
+            message(11, CAROL, 1, "unrelated"),
+            reply(message(12, ALICE, 2, "answer"), 10),
+        ];
+        let ids = MessageIndex::new(&messages);
+        assert_eq!(prompt(&messages, &ids, 2).as_deref(), Some("question"));
+        assert_eq!(
+            conversation(&messages, &ids, 2).unwrap(),
+            ["question", "answer"]
+        );
+    }
+
+    #[test]
+    fn remote_reference() {
+        let channels = [
+            Channel {
+                info: ChannelInfo::default(),
+                messages: vec![
+                    message(10, BOB, 0, "parent question"),
+                    message(11, CAROL, 1, "parent chatter"),
+                ],
+            },
+            Channel {
+                info: ChannelInfo::default(),
+                messages: vec![
+                    message(20, CAROL, 2, "thread chatter"),
+                    reply(message(21, ALICE, 3, "answer"), 10),
+                ],
+            },
+        ];
+        let archive = ArchiveIndex::new(&channels);
+        let messages = &channels[1].messages;
+        let ids = MessageIndex::with_archive(messages, &archive);
+        assert_eq!(
+            prompt(messages, &ids, 1).as_deref(),
+            Some("parent question")
+        );
+        assert_eq!(
+            conversation(messages, &ids, 1).unwrap(),
+            ["parent question", "answer"]
+        );
+        // Without the archive, the reference can't be followed.
+        let ids = MessageIndex::new(messages);
+        assert_eq!(prompt(messages, &ids, 1).as_deref(), Some("thread chatter"));
+    }
+
+    #[test]
+    fn context_stops_at_own_messages_and_gaps() {
+        let messages = [
+            message(10, CAROL, 0, "long ago"),
+            message(11, BOB, 30, "before"),
+            message(12, ALICE, 31, "mine"),
+            message(13, BOB, 32, "after"),
+            message(14, ALICE, 33, "reply"),
+        ];
+        let ids = MessageIndex::new(&messages);
+        assert_eq!(prompt(&messages, &ids, 4).as_deref(), Some("after"));
+        assert_eq!(prompt(&messages, &ids, 2).as_deref(), Some("before"));
+    }
+
+    fn thread(id: u64, name: &str, messages: Vec<Message>) -> Channel {
+        Channel {
+            info: ChannelInfo {
+                channel_id: Some(1000),
+                thread_id: Some(id),
+                thread_name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            messages,
+        }
+    }
+
+    fn thread_starter(max_messages: usize) -> ContextOptions {
+        ContextOptions {
+            strategy: ContextStrategy::ThreadStarter {
+                include_title: true,
+            },
+            max_messages,
+            max_gap: Duration::hours(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn thread_starter_counts_towards_max_messages() {
+        let thread = thread(
+            500,
+            "Lunch",
+            vec![
+                message(10, BOB, 0, "where to?"),
+                message(11, CAROL, 1, "pizza"),
+                message(12, BOB, 2, "again?"),
+                message(13, CAROL, 3, "always"),
+                message(14, ALICE, 4, "fine by me"),
+            ],
+        );
+        let messages = &thread.messages;
+        let ids = MessageIndex::new(messages).with_info(&thread.info);
+        let options = thread_starter(3);
+        assert_eq!(
+            get_prompt(messages, &ids, 4, ALICE, &options).as_deref(),
+            Some("Lunch\nwhere to?\nagain?\nalways")
+        );
+        let conversation = get_conversation(messages, &ids, 4, ALICE, &options).unwrap();
+        let turns: Vec<_> = conversation.turns.iter().map(|v| &v.content[..]).collect();
+        assert_eq!(
+            turns,
+            ["Lunch\nwhere to?", "again?", "always", "fine by me"]
+        );
+        // The opening post isn't repeated when the context reaches it.
+        let options = thread_starter(10);
+        assert_eq!(
+            get_prompt(messages, &ids, 4, ALICE, &options).as_deref(),
+            Some("Lunch\nwhere to?\npizza\nagain?\nalways")
+        );
+    }
+
+    #[test]
+    fn thread_starter_left_out_for_context_from_the_parent() {
+        let channels = [
+            Channel {
+                info: ChannelInfo {
+                    channel_id: Some(1000),
+                    ..Default::default()
+                },
+                messages: vec![message(10, BOB, 0, "parent question")],
+            },
+            thread(
+                500,
+                "Lunch",
+                vec![
+                    message(20, CAROL, 1, "where to?"),
+                    reply(message(21, ALICE, 2, "answer"), 10),
+                ],
+            ),
+        ];
+        let archive = ArchiveIndex::new(&channels);
+        let thread = &channels[1];
+        let ids = MessageIndex::with_archive(&thread.messages, &archive).with_info(&thread.info);
+        let options = thread_starter(5);
+        assert_eq!(
+            get_prompt(&thread.messages, &ids, 1, ALICE, &options).as_deref(),
+            Some("parent question")
+        );
+        let conversation = get_conversation(&thread.messages, &ids, 1, ALICE, &options).unwrap();
+        let turns: Vec<_> = conversation.turns.iter().map(|v| &v.content[..]).collect();
+        assert_eq!(turns, ["parent question", "answer"]);
+    }
+}
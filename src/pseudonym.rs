@@ -0,0 +1,80 @@
+//! Replacing user ids with anonymous labels such as `user_004217893520`.
+//!
+//! Labels are a keyed hash of the id, so the same user gets the same label in
+//! every channel and every run with the same key, but the id can't be worked
+//! out from the label without it.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    mentions::{replace_mentions, Mention},
+    model::{AuthorId, Message, Record},
+};
+
+/// Labels have this many decimal digits, enough that two users in even a
+/// very large archive are unlikely to share one.
+const LABEL_DIGITS: u32 = 12;
+
+/// Turns user ids into labels using a secret key.
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    mac: Hmac<Sha256>,
+}
+
+impl std::fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Don't print anything derived from the key.
+        f.debug_struct("Pseudonymizer").finish_non_exhaustive()
+    }
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// The label for `id`, such as `user_004217893520`.
+    pub fn label(&self, id: u64) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(&id.to_le_bytes());
+        let digest = mac.finalize().into_bytes();
+        let value = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        format!(
+            "user_{:0width$}",
+            value % 10u64.pow(LABEL_DIGITS),
+            width = LABEL_DIGITS as usize
+        )
+    }
+
+    /// Replace `<@id>` user mentions in every message's content with `@label`.
+    /// Role and channel mentions are left alone.
+    pub fn rewrite_messages(&self, messages: &mut [Message]) {
+        for message in messages {
+            if message.content.contains("<@") {
+                message.content = self.rewrite(&message.content);
+            }
+        }
+    }
+
+    /// Replace `<@id>` user mentions in `content` with `@label`.
+    pub fn rewrite(&self, content: &str) -> String {
+        replace_mentions(content, |mention| match mention {
+            Mention::User(id) => Some(format!("@{}", self.label(id))),
+            _ => None,
+        })
+    }
+
+    /// Replace the author ids in a record with labels.
+    pub fn record(&self, record: &mut Record) {
+        if let Record::Conversation(conversation) = record {
+            for turn in &mut conversation.turns {
+                if let AuthorId::Id(id) = turn.author {
+                    turn.author = AuthorId::Pseudonym(self.label(id));
+                }
+            }
+        }
+    }
+}